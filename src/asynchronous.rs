@@ -0,0 +1,195 @@
+//! A [`FileSync`] for async code, behind the `tokio` cargo feature
+//!
+//! Reading and writing the file happens on tokio's blocking thread pool through [`tokio::task::spawn_blocking`], so the executor threads never wait for the disk.
+//! The stored data itself is kept in memory as usual, so reading it doesn't need to be awaited
+
+use crate::shared::{SharedFileSync, SharedReadGuard};
+use crate::{FileSync, FileSyncError, Format, Json, Operation};
+use serde::{de::DeserializeOwned, Serialize};
+use std::path::{Path, PathBuf};
+
+/// A cheaply cloneable handle to a [`FileSync`] whose file I/O runs on tokio's blocking thread pool
+///
+/// It is a [`SharedFileSync`] underneath, so it can be shared between tasks, and has the same error semantics as the blocking methods it wraps.
+/// If a closure passed to [`AsyncFileSync::modify`] panics, the panic is resumed in the calling task
+pub struct AsyncFileSync<T, Fmt = Json>
+where
+    T: Serialize + DeserializeOwned,
+    Fmt: Format,
+{
+    inner: SharedFileSync<T, Fmt>,
+    /// The path of the file, for errors of the blocking thread pool itself
+    fp: PathBuf,
+}
+
+impl<T, Fmt> FileSync<T, Fmt>
+where
+    T: Serialize + DeserializeOwned,
+    Fmt: Format,
+{
+    /// Turns this `FileSync` into an [`AsyncFileSync`]
+    pub fn into_async(self) -> AsyncFileSync<T, Fmt> {
+        AsyncFileSync {
+            fp: self.path.clone(),
+            inner: self.into_shared(),
+        }
+    }
+}
+
+impl<T> AsyncFileSync<T>
+where
+    T: Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    /// Creates a new file with `data`. See [`FileSync::new`]
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if [`FileSync::new`] would
+    pub async fn new(fp: &Path, data: T, pretty: bool) -> Result<Self, FileSyncError> {
+        let fp = fp.to_path_buf();
+        spawn(Operation::Create, fp.clone(), move || {
+            FileSync::new(&fp, data, pretty)
+        })
+        .await
+        .map(FileSync::into_async)
+    }
+
+    /// Loads an existing file. See [`FileSync::load`]
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if [`FileSync::load`] would
+    pub async fn load(fp: &Path, pretty: bool) -> Result<Self, FileSyncError> {
+        let fp = fp.to_path_buf();
+        spawn(Operation::Load, fp.clone(), move || {
+            FileSync::load(&fp, pretty)
+        })
+        .await
+        .map(FileSync::into_async)
+    }
+
+    /// Loads the file if it exists, or creates it with `data` otherwise. See [`FileSync::load_or_new`]
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if [`FileSync::load_or_new`] would
+    pub async fn load_or_new(fp: &Path, data: T, pretty: bool) -> Result<Self, FileSyncError> {
+        let fp = fp.to_path_buf();
+        spawn(Operation::Load, fp.clone(), move || {
+            FileSync::load_or_new(&fp, data, pretty)
+        })
+        .await
+        .map(FileSync::into_async)
+    }
+}
+
+impl<T, Fmt> AsyncFileSync<T, Fmt>
+where
+    T: Serialize + DeserializeOwned + Send + Sync + 'static,
+    Fmt: Format + Send + Sync + 'static,
+{
+    /// Sets the value of the stored data. See [`FileSync::set`]
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if [`FileSync::set`] would
+    pub async fn set(&self, data: T) -> Result<(), FileSyncError> {
+        let inner = self.inner.clone();
+        spawn(Operation::Write, self.fp.clone(), move || inner.set(data)).await
+    }
+
+    /// Modifies data and syncs the modified data to the file. See [`FileSync::modify`]
+    ///
+    /// `f` runs on the blocking thread pool as well, since it runs while the data is locked
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if [`FileSync::modify`] would
+    pub async fn modify<F>(&self, f: F) -> Result<(), FileSyncError>
+    where
+        F: FnOnce(&mut T) + Send + 'static,
+    {
+        let inner = self.inner.clone();
+        spawn(Operation::Write, self.fp.clone(), move || inner.modify(f)).await
+    }
+
+    /// Replaces the stored data with the current contents of the file. See [`FileSync::reload`]
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if [`FileSync::reload`] would
+    pub async fn reload(&self) -> Result<(), FileSyncError> {
+        let inner = self.inner.clone();
+        spawn(Operation::Load, self.fp.clone(), move || inner.reload()).await
+    }
+}
+
+impl<T, Fmt> AsyncFileSync<T, Fmt>
+where
+    T: Serialize + DeserializeOwned,
+    Fmt: Format,
+{
+    /// Returns read access to the stored data. See [`SharedFileSync::read`]
+    ///
+    /// This doesn't touch the file, but waits while a write is changing or serializing the data
+    pub fn read(&self) -> SharedReadGuard<'_, T, Fmt> {
+        self.inner.read()
+    }
+
+    /// Returns the [`SharedFileSync`] underneath, for anything not covered by the other methods. Its methods block, so call them with [`tokio::task::spawn_blocking`]
+    pub fn shared(&self) -> &SharedFileSync<T, Fmt> {
+        &self.inner
+    }
+}
+
+impl<T, Fmt> Clone for AsyncFileSync<T, Fmt>
+where
+    T: Serialize + DeserializeOwned,
+    Fmt: Format,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            fp: self.fp.clone(),
+        }
+    }
+}
+
+impl<T, Fmt> From<FileSync<T, Fmt>> for AsyncFileSync<T, Fmt>
+where
+    T: Serialize + DeserializeOwned,
+    Fmt: Format,
+{
+    fn from(file_sync: FileSync<T, Fmt>) -> Self {
+        file_sync.into_async()
+    }
+}
+
+impl<T, Fmt> std::fmt::Debug for AsyncFileSync<T, Fmt>
+where
+    T: Serialize + DeserializeOwned + std::fmt::Debug,
+    Fmt: Format + std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AsyncFileSync")
+            .field("inner", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Runs `f` on the blocking thread pool
+///
+/// A panic in `f` is resumed here. If the runtime is shutting down and drops `f` before it ran, a [`FileSyncError::IoError`] for `op` on `fp` is returned
+async fn spawn<F, R>(op: Operation, fp: PathBuf, f: F) -> Result<R, FileSyncError>
+where
+    F: FnOnce() -> Result<R, FileSyncError> + Send + 'static,
+    R: Send + 'static,
+{
+    match tokio::task::spawn_blocking(f).await {
+        Ok(result) => result,
+        Err(e) => match e.try_into_panic() {
+            Ok(payload) => std::panic::resume_unwind(payload),
+            Err(e) => Err(FileSyncError::io(op, &fp, std::io::Error::other(e))),
+        },
+    }
+}
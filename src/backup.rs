@@ -0,0 +1,184 @@
+//! Keeping copies of the file from before each write
+
+use crate::{Context, FileSync, FileSyncError, Format, Operation};
+use serde::{de::DeserializeOwned, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// If and how a [`FileSync`] keeps copies of its file from before each write
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum BackupPolicy {
+    /// Don't keep backups
+    #[default]
+    None,
+    /// Keep the last `count` versions next to the file as `<file>.1` (the newest) up to `<file>.<count>`
+    ///
+    /// A `count` of 0 keeps no backups
+    Rotate { count: usize },
+    /// Keep copies in `dir` named `<file>.<nanoseconds since the unix epoch>.bak`, deleting the oldest ones once there are more than `keep`
+    ///
+    /// A `keep` of 0 keeps no backups and leaves existing ones in `dir` alone
+    Timestamped { dir: PathBuf, keep: usize },
+}
+
+impl<T, Fmt> FileSync<T, Fmt>
+where
+    T: Serialize + DeserializeOwned,
+    Fmt: Format,
+{
+    /// Returns the paths of the existing backups of the file, newest first. See [`FileSync::backups`]
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if reading the backup directory fails
+    pub fn list_backups(&self) -> Result<Vec<PathBuf>, FileSyncError> {
+        Self::backups_of(&self.path, &self.backups)
+    }
+
+    /// Returns the paths of the existing backups of the file at `fp` kept according to `policy`, newest first
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if reading the backup directory fails
+    pub(crate) fn backups_of(
+        fp: &Path,
+        policy: &BackupPolicy,
+    ) -> Result<Vec<PathBuf>, FileSyncError> {
+        match policy {
+            BackupPolicy::None => Ok(Vec::new()),
+            BackupPolicy::Rotate { count } => Ok((1..=*count)
+                .map(|n| Self::rotated_path(fp, n))
+                .take_while(|path| path.exists())
+                .collect()),
+            BackupPolicy::Timestamped { dir, .. } => Self::timestamped_backups(fp, dir),
+        }
+    }
+
+    /// Replaces the stored data with the contents of the `n`th newest backup and writes it to the file, starting at 1. See [`FileSync::list_backups`]
+    ///
+    /// The data is written through [`FileSync::set`], so the current file is backed up first and the restore can be undone
+    ///
+    /// # Errors
+    ///
+    /// Will return [`FileSyncError::BackupNotFound`] if there is no `n`th backup
+    ///
+    /// Will return an error if reading the backup fails or [`Format::deserialize`] returns an error
+    ///
+    /// Will return an error if [`FileSync::set`] returns an error
+    pub fn restore_backup(&mut self, n: usize) -> Result<(), FileSyncError> {
+        let backups = self.list_backups()?;
+        let path = n
+            .checked_sub(1)
+            .and_then(|index| backups.get(index))
+            .ok_or(FileSyncError::BackupNotFound { n })?;
+        let bytes = std::fs::read(path).context(Operation::Load, path)?;
+        let data = self
+            .format
+            .deserialize(bytes.as_slice())
+            .map_err(|e| FileSyncError::from_format(e, Operation::Load, path))?;
+        self.set(data)
+    }
+
+    /// Copies the current file to a new backup according to [`FileSync::backups`], dropping the oldest backups over the limit
+    ///
+    /// Does nothing if the file doesn't exist
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if copying, renaming or deleting a file fails
+    pub(crate) fn back_up(&self) -> Result<(), FileSyncError> {
+        if !self.path.exists() {
+            return Ok(());
+        }
+        match &self.backups {
+            BackupPolicy::None => {}
+            BackupPolicy::Rotate { count: 0 } => {}
+            BackupPolicy::Timestamped { keep: 0, .. } => {}
+            BackupPolicy::Rotate { count } => {
+                let oldest = Self::rotated_path(&self.path, *count);
+                remove_if_exists(&oldest).context(Operation::Write, &oldest)?;
+                for n in (1..*count).rev() {
+                    let from = Self::rotated_path(&self.path, n);
+                    if from.exists() {
+                        let to = Self::rotated_path(&self.path, n.saturating_add(1));
+                        std::fs::rename(from, &to).context(Operation::Write, &to)?;
+                    }
+                }
+                let newest = Self::rotated_path(&self.path, 1);
+                std::fs::copy(&self.path, &newest).context(Operation::Write, &newest)?;
+            }
+            BackupPolicy::Timestamped { dir, keep } => {
+                std::fs::create_dir_all(dir).context(Operation::Write, dir)?;
+                let nanos = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_nanos();
+                let mut name = file_name(&self.path);
+                name.push(format!(".{nanos:020}.bak"));
+                let backup = dir.join(name);
+                std::fs::copy(&self.path, &backup).context(Operation::Write, &backup)?;
+                for old in Self::timestamped_backups(&self.path, dir)?
+                    .iter()
+                    .skip(*keep)
+                {
+                    remove_if_exists(old).context(Operation::Write, old)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the path of the `n`th rotated backup of the file at `fp`, `<file>.<n>`
+    fn rotated_path(fp: &Path, n: usize) -> PathBuf {
+        let mut name = file_name(fp);
+        name.push(format!(".{n}"));
+        fp.with_file_name(name)
+    }
+
+    /// Returns the timestamped backups of the file at `fp` in `dir`, newest first
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if reading `dir` fails
+    fn timestamped_backups(fp: &Path, dir: &Path) -> Result<Vec<PathBuf>, FileSyncError> {
+        let mut prefix = file_name(fp);
+        prefix.push(".");
+        let prefix = prefix.to_string_lossy().into_owned();
+        let mut backups = Vec::new();
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(backups),
+            Err(e) => return Err(FileSyncError::io(Operation::Load, dir, e)),
+        };
+        for entry in entries {
+            let entry = entry.context(Operation::Load, dir)?;
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if let Some(timestamp) = name
+                .strip_prefix(&prefix)
+                .and_then(|rest| rest.strip_suffix(".bak"))
+            {
+                if !timestamp.is_empty() && timestamp.bytes().all(|b| b.is_ascii_digit()) {
+                    backups.push(entry.path());
+                }
+            }
+        }
+        // The timestamps are zero padded, so sorting by name sorts by time
+        backups.sort_unstable_by(|a, b| b.cmp(a));
+        Ok(backups)
+    }
+}
+
+fn file_name(fp: &Path) -> std::ffi::OsString {
+    fp.file_name().unwrap_or_default().to_os_string()
+}
+
+/// # Errors
+///
+/// Will return an error if deleting `path` fails for any reason other than it not existing
+fn remove_if_exists(path: &Path) -> std::io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
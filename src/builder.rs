@@ -0,0 +1,257 @@
+//! Building a [`FileSync`] with options, as an alternative to the positional arguments of [`FileSync::new`] and friends
+
+use crate::checksum;
+use crate::format::{Format, IndentedJson, Json};
+use crate::{Context, FileSync, FileSyncError, Locking, Opened, Operation, Path};
+use serde::{de::DeserializeOwned, Serialize};
+use std::fs::Permissions;
+use std::marker::PhantomData;
+
+/// Options for creating or opening a [`FileSync`], returned by [`FileSync::builder`]
+///
+/// Options are chained, and building finishes with [`FileSyncBuilder::create`], [`FileSyncBuilder::open`] or [`FileSyncBuilder::open_or_create`]
+#[derive(Debug)]
+pub struct FileSyncBuilder<'a, T, Fmt = Json> {
+    fp: &'a Path,
+    format: Fmt,
+    pretty: bool,
+    permissions: Option<Permissions>,
+    create_dirs: bool,
+    atomic: bool,
+    locking: Option<Locking>,
+    on_invalid: OnInvalidFile,
+    _data: PhantomData<fn() -> T>,
+}
+
+/// What [`FileSyncBuilder::open_or_create`] does when the existing file can't be parsed. See [`FileSyncBuilder::on_invalid`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OnInvalidFile {
+    /// Return the error
+    #[default]
+    Error,
+    /// Delete the file and create it again with the default data
+    Replace,
+    /// Rename the file to `<file>.corrupt` and create it again with the default data
+    BackUpAndReplace,
+}
+
+impl<T> FileSync<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    /// Returns a builder for a `FileSync` syncing the file at `fp`. See [`FileSyncBuilder`]
+    pub fn builder(fp: &Path) -> FileSyncBuilder<'_, T> {
+        FileSyncBuilder {
+            fp,
+            format: Json,
+            pretty: false,
+            permissions: None,
+            create_dirs: false,
+            atomic: true,
+            locking: None,
+            on_invalid: OnInvalidFile::default(),
+            _data: PhantomData,
+        }
+    }
+}
+
+impl<'a, T> FileSyncBuilder<'a, T, Json>
+where
+    T: Serialize + DeserializeOwned,
+{
+    /// Writes pretty JSON indented with `indent` instead of two spaces. Switches the format to [`IndentedJson`] and turns on [`FileSyncBuilder::pretty`]
+    #[must_use]
+    pub fn indent(self, indent: &str) -> FileSyncBuilder<'a, T, IndentedJson> {
+        self.format(IndentedJson::new(indent)).pretty()
+    }
+}
+
+impl<'a, T, Fmt> FileSyncBuilder<'a, T, Fmt>
+where
+    T: Serialize + DeserializeOwned,
+    Fmt: Format,
+{
+    /// Writes human readable output. See [`FileSync::pretty`]
+    #[must_use]
+    pub fn pretty(mut self) -> Self {
+        self.pretty = true;
+        self
+    }
+
+    /// Writes compact output. This is the default. See [`FileSync::pretty`]
+    #[must_use]
+    pub fn compact(mut self) -> Self {
+        self.pretty = false;
+        self
+    }
+
+    /// Stores the data as `format` instead of [`Json`]
+    #[must_use]
+    pub fn format<F>(self, format: F) -> FileSyncBuilder<'a, T, F>
+    where
+        F: Format,
+    {
+        FileSyncBuilder {
+            fp: self.fp,
+            format,
+            pretty: self.pretty,
+            permissions: self.permissions,
+            create_dirs: self.create_dirs,
+            atomic: self.atomic,
+            locking: self.locking,
+            on_invalid: self.on_invalid,
+            _data: PhantomData,
+        }
+    }
+
+    /// Gives a newly created file `permissions` before anything is written to it. Atomic writes keep the permissions of the file they replace
+    ///
+    /// Files that already exist keep their permissions
+    #[must_use]
+    pub fn permissions(mut self, permissions: Permissions) -> Self {
+        self.permissions = Some(permissions);
+        self
+    }
+
+    /// Creates the missing parent directories of the file when creating it
+    ///
+    /// Defaults to `false`
+    #[must_use]
+    pub fn create_dirs(mut self, create_dirs: bool) -> Self {
+        self.create_dirs = create_dirs;
+        self
+    }
+
+    /// See [`FileSync::atomic`]
+    ///
+    /// Defaults to `true`
+    #[must_use]
+    pub fn atomic(mut self, atomic: bool) -> Self {
+        self.atomic = atomic;
+        self
+    }
+
+    /// Turns on advisory locking. See [`FileSync::set_locking`]
+    #[must_use]
+    pub fn locking(mut self, locking: Locking) -> Self {
+        self.locking = Some(locking);
+        self
+    }
+
+    /// What [`FileSyncBuilder::open_or_create`] does when the existing file can't be deserialized, instead of returning the error
+    ///
+    /// Only files that can't be parsed at all count, which are JSON syntax errors, truncated files, a [`FileSyncError::ChecksumMismatch`] and a malformed checksum header.
+    /// Files that parse but don't match `T`, are from an unsupported version, can't be migrated or can't be decrypted are never replaced, since they might be written by a newer version of the program
+    ///
+    /// Defaults to [`OnInvalidFile::Error`]
+    #[must_use]
+    pub fn on_invalid(mut self, on_invalid: OnInvalidFile) -> Self {
+        self.on_invalid = on_invalid;
+        self
+    }
+
+    /// Creates the file with `data`, like [`FileSync::new_with_format`]
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if creating the parent directories fails
+    ///
+    /// Will return an error if [`FileSync::new_with_format`] or [`FileSync::set_locking`] returns an error
+    pub fn create(self, data: T) -> Result<FileSync<T, Fmt>, FileSyncError> {
+        if self.create_dirs {
+            if let Some(parent) = self.fp.parent() {
+                std::fs::create_dir_all(parent).context(Operation::Create, parent)?;
+            }
+        }
+        let file_sync =
+            FileSync::create_with(self.fp, data, self.pretty, self.format, self.permissions)?;
+        Self::configure(file_sync, self.atomic, self.locking)
+    }
+
+    /// Loads the existing file, like [`FileSync::load_with_format`]
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if [`FileSync::load_with_format`] or [`FileSync::set_locking`] returns an error
+    pub fn open(self) -> Result<FileSync<T, Fmt>, FileSyncError> {
+        let file_sync = FileSync::load_with_format(self.fp, self.pretty, self.format)?;
+        Self::configure(file_sync, self.atomic, self.locking)
+    }
+
+    /// Loads the file if it exists, or creates it with the data returned by `default` otherwise. `default` is only called if the file is created
+    ///
+    /// If another process creates the file at the same time, its data is loaded instead, see [`FileSync::load_or_new`]
+    ///
+    /// If the file can't be deserialized, it is replaced with the data returned by `default` depending on [`FileSyncBuilder::on_invalid`]
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if [`FileSyncBuilder::open`] or [`FileSyncBuilder::create`] returns an error
+    ///
+    /// Will return an error if deleting or renaming an invalid file fails
+    pub fn open_or_create<F>(self, default: F) -> Result<FileSync<T, Fmt>, FileSyncError>
+    where
+        F: FnOnce() -> T,
+    {
+        if self.create_dirs {
+            if let Some(parent) = self.fp.parent() {
+                std::fs::create_dir_all(parent).context(Operation::Create, parent)?;
+            }
+        }
+        let opened = FileSync::read_or_create(self.fp, &self.format, self.permissions.clone());
+        let contents = match opened {
+            Ok(Opened::Created(file)) => {
+                let file_sync =
+                    FileSync::from_new_file(self.fp, file, default(), self.pretty, self.format)?;
+                return Self::configure(file_sync, self.atomic, self.locking);
+            }
+            Ok(Opened::Existing(contents)) => Some(contents),
+            Err(e) if self.on_invalid != OnInvalidFile::Error && is_unreadable(&e) => None,
+            Err(e) => return Err(e),
+        };
+        match contents {
+            Some(contents) => {
+                let file_sync =
+                    FileSync::from_contents(self.fp, contents, self.pretty, self.format)?;
+                Self::configure(file_sync, self.atomic, self.locking)
+            }
+            None => {
+                if self.on_invalid == OnInvalidFile::BackUpAndReplace {
+                    let mut name = self.fp.file_name().unwrap_or_default().to_os_string();
+                    name.push(".corrupt");
+                    let corrupt = self.fp.with_file_name(name);
+                    std::fs::rename(self.fp, &corrupt).context(Operation::Write, &corrupt)?;
+                } else {
+                    std::fs::remove_file(self.fp).context(Operation::Clear, self.fp)?;
+                }
+                self.create(default())
+            }
+        }
+    }
+
+    /// # Errors
+    ///
+    /// Will return an error if [`FileSync::set_locking`] returns an error
+    fn configure(
+        mut file_sync: FileSync<T, Fmt>,
+        atomic: bool,
+        locking: Option<Locking>,
+    ) -> Result<FileSync<T, Fmt>, FileSyncError> {
+        file_sync.atomic = atomic;
+        if locking.is_some() {
+            file_sync.set_locking(locking)?;
+        }
+        Ok(file_sync)
+    }
+}
+
+/// Returns `true` if `e` means the contents of the file can't be parsed at all, as opposed to being valid data that can't be used
+fn is_unreadable(e: &FileSyncError) -> bool {
+    match e {
+        FileSyncError::SerdeJsonError { source, .. } => matches!(
+            source.classify(),
+            serde_json::error::Category::Syntax | serde_json::error::Category::Eof
+        ),
+        e => checksum::is_corrupted(e),
+    }
+}
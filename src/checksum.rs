@@ -0,0 +1,268 @@
+//! Detecting corrupted files with a checksum
+//!
+//! A [`Checksummed`] format writes a header line with the CRC-32 of the payload, `CRC32:1a2b3c4d`, followed by the payload written by the inner format.
+//! Reading a file whose payload doesn't match its checksum fails with [`FileSyncError::ChecksumMismatch`], and [`FileSync::load_or_restore_with_format`] can fall back to the newest backup that is intact
+//!
+//! To combine it with [`Versioned`](crate::Versioned), wrap the `Versioned` format in a `Checksummed` one so the mismatch can be recognized
+
+use crate::format::{Format, Json};
+use crate::{BackupPolicy, Context, FileSync, FileSyncError, Operation, Path};
+use serde::{de::DeserializeOwned, Serialize};
+use std::io::{Read, Write};
+
+const HEADER_PREFIX: &[u8] = b"CRC32:";
+
+/// A [`Format`] that prefixes the output of another format with its checksum, and verifies it on read. See the [module level docs](self)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checksummed<Fmt = Json> {
+    inner: Fmt,
+    /// If files without a checksum are read without verifying them, so existing files can be switched over. They get a checksum the next time they are written
+    ///
+    /// Defaults to `true`
+    pub allow_missing: bool,
+}
+
+impl<Fmt> Checksummed<Fmt>
+where
+    Fmt: Format,
+{
+    /// Creates a format that checksums what `inner` writes
+    pub fn new(inner: Fmt) -> Self {
+        Self {
+            inner,
+            allow_missing: true,
+        }
+    }
+}
+
+impl<Fmt> Default for Checksummed<Fmt>
+where
+    Fmt: Format + Default,
+{
+    fn default() -> Self {
+        Self::new(Fmt::default())
+    }
+}
+
+impl<Fmt> Format for Checksummed<Fmt>
+where
+    Fmt: Format,
+{
+    type Error = ChecksumError;
+
+    fn serialize<W, T>(&self, mut writer: W, value: &T, pretty: bool) -> Result<(), Self::Error>
+    where
+        W: Write,
+        T: Serialize + ?Sized,
+    {
+        let mut payload = Vec::new();
+        self.inner
+            .serialize(&mut payload, value, pretty)
+            .map_err(|e| ChecksumError::Format(Box::new(e)))?;
+        writer.write_all(HEADER_PREFIX)?;
+        writeln!(writer, "{:08x}", crc32(&payload))?;
+        writer.write_all(&payload)?;
+        Ok(())
+    }
+
+    fn deserialize<R, T>(&self, mut reader: R) -> Result<T, Self::Error>
+    where
+        R: Read,
+        T: DeserializeOwned,
+    {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        let payload = match bytes.strip_prefix(HEADER_PREFIX) {
+            Some(rest) => {
+                let (header, payload) = rest
+                    .iter()
+                    .position(|&b| b == b'\n')
+                    .and_then(|end| Some((rest.get(..end)?, rest.get(end.saturating_add(1)..)?)))
+                    .ok_or(ChecksumError::InvalidHeader)?;
+                let expected = std::str::from_utf8(header)
+                    .ok()
+                    .and_then(|header| u32::from_str_radix(header, 16).ok())
+                    .ok_or(ChecksumError::InvalidHeader)?;
+                let actual = crc32(payload);
+                if expected != actual {
+                    return Err(ChecksumError::Mismatch { expected, actual });
+                }
+                payload
+            }
+            None if self.allow_missing => bytes.as_slice(),
+            None => return Err(ChecksumError::Missing),
+        };
+        self.inner
+            .deserialize(payload)
+            .map_err(|e| ChecksumError::Format(Box::new(e)))
+    }
+
+    fn take_migration(&self) -> Option<crate::versioning::Migration> {
+        self.inner.take_migration()
+    }
+
+    fn encrypts(&self) -> bool {
+        self.inner.encrypts()
+    }
+}
+
+/// The error type of [`Checksummed`]
+#[derive(thiserror::Error, Debug)]
+pub enum ChecksumError {
+    #[error("Checksum is {actual:08x}, but {expected:08x} was stored")]
+    Mismatch { expected: u32, actual: u32 },
+    #[error("File has no checksum")]
+    Missing,
+    #[error("Checksum header is malformed")]
+    InvalidHeader,
+    #[error("IO error")]
+    Io(#[from] std::io::Error),
+    #[error("Inner format error")]
+    Format(#[source] Box<dyn std::error::Error + Send + Sync>),
+}
+
+/// Returns `true` if `e` means a [`Checksummed`] file is corrupted, because its checksum doesn't match or its header is malformed
+pub(crate) fn is_corrupted(e: &FileSyncError) -> bool {
+    match e {
+        FileSyncError::ChecksumMismatch { .. } => true,
+        FileSyncError::FormatError { source, .. } => matches!(
+            source.downcast_ref::<ChecksumError>(),
+            Some(ChecksumError::InvalidHeader)
+        ),
+        _ => false,
+    }
+}
+
+/// CRC-32 as used by zlib and PNG
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let crc = bytes.iter().fold(u32::MAX, |crc, &b| {
+        (0..8).fold(crc ^ u32::from(b), |crc, _| {
+            if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            }
+        })
+    });
+    !crc
+}
+
+impl<T, Fmt> FileSync<T, Fmt>
+where
+    T: Serialize + DeserializeOwned,
+    Fmt: Format,
+{
+    /// [`FileSync::load_with_format`], but if the file fails its checksum or its checksum header is malformed, the newest backup kept according to `backups` that passes is restored and loaded instead
+    ///
+    /// The corrupted file is kept as `<file>.corrupt`. The returned `FileSync` keeps backups according to `backups`
+    ///
+    /// # Errors
+    ///
+    /// Will return [`FileSyncError::ChecksumMismatch`] if the file and all of its backups fail their checksums, or [`FileSyncError::FormatError`] with a
+    /// [`ChecksumError::InvalidHeader`] if the file's header is malformed and none of its backups are intact
+    ///
+    /// Will return an error if [`FileSync::load_with_format`] would, or if restoring a backup fails
+    pub fn load_or_restore_with_format(
+        fp: &Path,
+        pretty: bool,
+        format: Fmt,
+        backups: BackupPolicy,
+    ) -> Result<Self, FileSyncError> {
+        let contents = match Self::read_file(fp, &format) {
+            Err(e) if is_corrupted(&e) => {
+                if !Self::restore_intact_backup(fp, &format, &backups)? {
+                    return Err(e);
+                }
+                Self::read_file(fp, &format)?
+            }
+            contents => contents?,
+        };
+        let mut file_sync = Self::from_contents(fp, contents, pretty, format)?;
+        file_sync.backups = backups;
+        Ok(file_sync)
+    }
+
+    /// Replaces the file at `fp` with the newest of its backups that can be read, copying it to `<file>.corrupt` first. Returns `false` if there was no such backup
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if listing the backups or replacing the file fails
+    fn restore_intact_backup(
+        fp: &Path,
+        format: &Fmt,
+        backups: &BackupPolicy,
+    ) -> Result<bool, FileSyncError> {
+        for backup in Self::backups_of(fp, backups)? {
+            if Self::read_file(&backup, format).is_ok() {
+                let mut name = fp.file_name().unwrap_or_default().to_os_string();
+                name.push(".corrupt");
+                let corrupt = fp.with_file_name(name);
+                std::fs::copy(fp, &corrupt).context(Operation::Write, &corrupt)?;
+                let bytes = std::fs::read(&backup).context(Operation::Load, &backup)?;
+                Self::write_atomic(fp, &bytes).context(Operation::Write, fp)?;
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn round_trips() {
+        let format = Checksummed::new(Json);
+        let mut bytes = Vec::new();
+        format.serialize(&mut bytes, &[1, 2, 3], false).unwrap();
+
+        assert_eq!(
+            bytes,
+            format!("CRC32:{:08x}\n[1,2,3]", crc32(b"[1,2,3]")).as_bytes()
+        );
+        let value: Vec<i32> = format.deserialize(bytes.as_slice()).unwrap();
+        assert_eq!(value, [1, 2, 3]);
+    }
+
+    #[test]
+    fn detects_corruption() {
+        let format = Checksummed::new(Json);
+        let expected = crc32(b"[1,2,3]");
+        let bytes = format!("CRC32:{expected:08x}\n[1,2,4]");
+
+        let result = format.deserialize::<_, Vec<i32>>(bytes.as_bytes());
+
+        assert!(matches!(
+            result,
+            Err(ChecksumError::Mismatch { expected: e, actual })
+                if e == expected && actual == crc32(b"[1,2,4]")
+        ));
+    }
+
+    #[test]
+    fn rejects_malformed_header() {
+        let format = Checksummed::new(Json);
+        for bytes in [&b"CRC32:0123abcd[1,2,3]"[..], b"CRC32:nothex\n[1,2,3]"] {
+            let result = format.deserialize::<_, Vec<i32>>(bytes);
+            assert!(matches!(result, Err(ChecksumError::InvalidHeader)));
+        }
+    }
+
+    #[test]
+    fn missing_checksum() {
+        let mut format = Checksummed::new(Json);
+        let value: Vec<i32> = format.deserialize(&b"[1,2,3]"[..]).unwrap();
+        assert_eq!(value, [1, 2, 3]);
+
+        format.allow_missing = false;
+        let result = format.deserialize::<_, Vec<i32>>(&b"[1,2,3]"[..]);
+        assert!(matches!(result, Err(ChecksumError::Missing)));
+    }
+}
@@ -0,0 +1,102 @@
+//! Compressing files
+//!
+//! A [`Compressed`] format compresses the output of another format with a [`Codec`]. On read, the file is only decompressed if it starts with the magic bytes of the codec,
+//! so existing uncompressed files can be switched over without a migration. They are compressed the next time they are written
+//!
+//! No codecs are built in yet. Implementing [`Codec`] is usually a thin wrapper around the encoder and decoder of a compression crate, like `flate2` for gzip (magic bytes `1f 8b`) or `zstd` (magic bytes `28 b5 2f fd`).
+//! Built-in gzip and zstd codecs behind cargo features, and picking the codec of a file from its magic bytes on load, are still open
+
+use crate::format::{Format, Json};
+use serde::{de::DeserializeOwned, Serialize};
+use std::io::{Read, Write};
+
+/// A compression algorithm used by [`Compressed`]
+pub trait Codec {
+    /// The bytes every compressed output starts with, used to tell compressed files from uncompressed ones
+    fn magic(&self) -> &[u8];
+
+    /// Compresses `bytes`
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if compressing fails
+    fn compress(&self, bytes: &[u8]) -> std::io::Result<Vec<u8>>;
+
+    /// Decompresses `bytes`
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if `bytes` aren't valid compressed data
+    fn decompress(&self, bytes: &[u8]) -> std::io::Result<Vec<u8>>;
+}
+
+/// A [`Format`] that compresses what another format writes. See the [module level docs](self)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Compressed<C, Fmt = Json> {
+    codec: C,
+    inner: Fmt,
+}
+
+impl<C, Fmt> Compressed<C, Fmt>
+where
+    C: Codec,
+    Fmt: Format,
+{
+    /// Creates a format that compresses what `inner` writes with `codec`
+    pub fn new(codec: C, inner: Fmt) -> Self {
+        Self { codec, inner }
+    }
+}
+
+impl<C, Fmt> Format for Compressed<C, Fmt>
+where
+    C: Codec,
+    Fmt: Format,
+{
+    type Error = CompressionError;
+
+    fn serialize<W, T>(&self, mut writer: W, value: &T, pretty: bool) -> Result<(), Self::Error>
+    where
+        W: Write,
+        T: Serialize + ?Sized,
+    {
+        let mut bytes = Vec::new();
+        self.inner
+            .serialize(&mut bytes, value, pretty)
+            .map_err(|e| CompressionError::Format(Box::new(e)))?;
+        writer.write_all(&self.codec.compress(&bytes)?)?;
+        Ok(())
+    }
+
+    fn deserialize<R, T>(&self, mut reader: R) -> Result<T, Self::Error>
+    where
+        R: Read,
+        T: DeserializeOwned,
+    {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        if bytes.starts_with(self.codec.magic()) {
+            bytes = self.codec.decompress(&bytes)?;
+        }
+        self.inner
+            .deserialize(bytes.as_slice())
+            .map_err(|e| CompressionError::Format(Box::new(e)))
+    }
+
+    fn take_migration(&self) -> Option<crate::versioning::Migration> {
+        self.inner.take_migration()
+    }
+
+    fn encrypts(&self) -> bool {
+        self.inner.encrypts()
+    }
+}
+
+/// The error type of [`Compressed`]
+#[derive(thiserror::Error, Debug)]
+pub enum CompressionError {
+    #[error("Compression IO error")]
+    Io(#[from] std::io::Error),
+    #[error("Inner format error")]
+    Format(#[source] Box<dyn std::error::Error + Send + Sync>),
+}
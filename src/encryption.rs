@@ -0,0 +1,156 @@
+//! Encrypting files at rest
+//!
+//! An [`Encrypted`] format encrypts the output of another format with a [`Cipher`]. The file starts with a header line holding the id of the key it was encrypted with, `FSENC:<hex key id>`,
+//! followed by the ciphertext. A file encrypted with a different key fails with [`FileSyncError::WrongKey`](crate::FileSyncError::WrongKey),
+//! and one whose ciphertext fails to decrypt, because it was tampered with or corrupted, fails with [`FileSyncError::DecryptionFailed`](crate::FileSyncError::DecryptionFailed)
+//!
+//! The [`journal`](crate::journal) and a persisted undo history are written as plain JSON no matter the format, so they would leak the data of an encrypted file.
+//! [`FileSync::set_journal`](crate::FileSync::set_journal) and [`FileSync::set_history`](crate::FileSync::set_history) refuse to turn them on and return [`FileSyncError::Plaintext`](crate::FileSyncError::Plaintext) instead
+//!
+//! No ciphers are built in yet. Implementing [`Cipher`] is usually a thin wrapper around an authenticated encryption crate like `chacha20poly1305` or `aes-gcm`, with the key given by the caller or derived from a passphrase with a crate like `argon2`.
+//! A built-in authenticated cipher and passphrase derivation behind an `encryption` cargo feature are still open
+
+use crate::format::{Format, Json};
+use serde::{de::DeserializeOwned, Serialize};
+use std::io::{Read, Write};
+
+const HEADER_PREFIX: &[u8] = b"FSENC:";
+
+/// An authenticated encryption algorithm and key used by [`Encrypted`]
+pub trait Cipher {
+    /// Identifies the key without revealing it, for example a hash of it. It is stored in every file so a wrong key can be told apart from tampering
+    fn key_id(&self) -> Vec<u8>;
+
+    /// Encrypts `plaintext`, including anything needed to decrypt it other than the key, like the nonce, in the output
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if encrypting fails
+    fn encrypt(
+        &self,
+        plaintext: &[u8],
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>>;
+
+    /// Decrypts and authenticates `ciphertext`
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if `ciphertext` fails to authenticate or decrypt
+    fn decrypt(
+        &self,
+        ciphertext: &[u8],
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>>;
+}
+
+/// A [`Format`] that encrypts what another format writes. See the [module level docs](self)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Encrypted<C, Fmt = Json> {
+    cipher: C,
+    inner: Fmt,
+    /// If files that aren't encrypted are read as they are, so existing files can be switched over. They are encrypted the next time they are written
+    ///
+    /// Defaults to `false`, since otherwise anyone who can write the file can replace its contents
+    pub allow_unencrypted: bool,
+}
+
+impl<C, Fmt> Encrypted<C, Fmt>
+where
+    C: Cipher,
+    Fmt: Format,
+{
+    /// Creates a format that encrypts what `inner` writes with `cipher`
+    pub fn new(cipher: C, inner: Fmt) -> Self {
+        Self {
+            cipher,
+            inner,
+            allow_unencrypted: false,
+        }
+    }
+}
+
+impl<C, Fmt> Format for Encrypted<C, Fmt>
+where
+    C: Cipher,
+    Fmt: Format,
+{
+    type Error = EncryptionError;
+
+    fn serialize<W, T>(&self, mut writer: W, value: &T, pretty: bool) -> Result<(), Self::Error>
+    where
+        W: Write,
+        T: Serialize + ?Sized,
+    {
+        let mut plaintext = Vec::new();
+        self.inner
+            .serialize(&mut plaintext, value, pretty)
+            .map_err(|e| EncryptionError::Format(Box::new(e)))?;
+        let ciphertext = self
+            .cipher
+            .encrypt(&plaintext)
+            .map_err(EncryptionError::Encrypt)?;
+        writer.write_all(HEADER_PREFIX)?;
+        writeln!(writer, "{}", to_hex(&self.cipher.key_id()))?;
+        writer.write_all(&ciphertext)?;
+        Ok(())
+    }
+
+    fn deserialize<R, T>(&self, mut reader: R) -> Result<T, Self::Error>
+    where
+        R: Read,
+        T: DeserializeOwned,
+    {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        let plaintext = match bytes.strip_prefix(HEADER_PREFIX) {
+            Some(rest) => {
+                let (key_id, ciphertext) = rest
+                    .iter()
+                    .position(|&b| b == b'\n')
+                    .and_then(|end| Some((rest.get(..end)?, rest.get(end.saturating_add(1)..)?)))
+                    .ok_or(EncryptionError::InvalidHeader)?;
+                if key_id != to_hex(&self.cipher.key_id()).as_bytes() {
+                    return Err(EncryptionError::WrongKey);
+                }
+                self.cipher
+                    .decrypt(ciphertext)
+                    .map_err(EncryptionError::Decrypt)?
+            }
+            None if self.allow_unencrypted => bytes,
+            None => return Err(EncryptionError::NotEncrypted),
+        };
+        self.inner
+            .deserialize(plaintext.as_slice())
+            .map_err(|e| EncryptionError::Format(Box::new(e)))
+    }
+
+    fn take_migration(&self) -> Option<crate::versioning::Migration> {
+        self.inner.take_migration()
+    }
+
+    fn encrypts(&self) -> bool {
+        true
+    }
+}
+
+/// The error type of [`Encrypted`]
+#[derive(thiserror::Error, Debug)]
+pub enum EncryptionError {
+    #[error("File was encrypted with a different key")]
+    WrongKey,
+    #[error("Decryption failed, the file was tampered with or corrupted")]
+    Decrypt(#[source] Box<dyn std::error::Error + Send + Sync>),
+    #[error("Encryption failed")]
+    Encrypt(#[source] Box<dyn std::error::Error + Send + Sync>),
+    #[error("File isn't encrypted")]
+    NotEncrypted,
+    #[error("Encryption header is malformed")]
+    InvalidHeader,
+    #[error("IO error")]
+    Io(#[from] std::io::Error),
+    #[error("Inner format error")]
+    Format(#[source] Box<dyn std::error::Error + Send + Sync>),
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
@@ -0,0 +1,126 @@
+//! Serialization formats a [`FileSync`](crate::FileSync) can store its data in
+
+use crate::versioning::Migration;
+use serde::{de::DeserializeOwned, Serialize};
+use std::io::{Read, Write};
+
+/// A serialization format used to write the data of a [`FileSync`](crate::FileSync) to its file and read it back
+///
+/// [`Json`] is the default. Other formats can be used by implementing this trait, usually as a thin wrapper around the `to_writer`/`from_reader` functions of a serde format crate.
+/// Built-in TOML, RON, YAML, bincode and MessagePack formats behind cargo features are still open
+pub trait Format {
+    /// The error returned when serializing or deserializing fails
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    /// Serializes `value` into `writer`
+    ///
+    /// `pretty` asks for human readable output. Formats that have no such distinction can ignore it
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if `value` can't be serialized or writing to `writer` fails
+    fn serialize<W, T>(&self, writer: W, value: &T, pretty: bool) -> Result<(), Self::Error>
+    where
+        W: Write,
+        T: Serialize + ?Sized;
+
+    /// Deserializes a value from `reader`
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if reading from `reader` fails or its contents can't be deserialized into a `T`
+    fn deserialize<R, T>(&self, reader: R) -> Result<T, Self::Error>
+    where
+        R: Read,
+        T: DeserializeOwned;
+
+    /// Returns the migration done by the last call to [`Format::deserialize`], if any, and forgets it
+    ///
+    /// [`FileSync`](crate::FileSync) uses this to back up and write back files that were migrated on load.
+    /// Only formats that migrate, like [`Versioned`](crate::versioning::Versioned), need to override the default
+    fn take_migration(&self) -> Option<Migration> {
+        None
+    }
+
+    /// Returns `true` if the format encrypts the data, so it must not be written anywhere else in plain text
+    ///
+    /// [`FileSync::set_journal`](crate::FileSync::set_journal) and persisting the history with [`FileSync::set_history`](crate::FileSync::set_history) are refused for such formats.
+    /// Formats that wrap another format should return what the inner format returns
+    fn encrypts(&self) -> bool {
+        false
+    }
+}
+
+/// JSON through [`serde_json`]
+///
+/// Uses [`serde_json::to_writer_pretty`] when `pretty` is `true` and [`serde_json::to_writer`] otherwise
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Json;
+
+impl Format for Json {
+    type Error = serde_json::Error;
+
+    fn serialize<W, T>(&self, writer: W, value: &T, pretty: bool) -> Result<(), Self::Error>
+    where
+        W: Write,
+        T: Serialize + ?Sized,
+    {
+        if pretty {
+            serde_json::to_writer_pretty(writer, value)
+        } else {
+            serde_json::to_writer(writer, value)
+        }
+    }
+
+    fn deserialize<R, T>(&self, reader: R) -> Result<T, Self::Error>
+    where
+        R: Read,
+        T: DeserializeOwned,
+    {
+        serde_json::from_reader(reader)
+    }
+}
+
+/// JSON through [`serde_json`], with pretty output indented by a custom string instead of two spaces
+///
+/// Uses [`serde_json::ser::PrettyFormatter::with_indent`] when `pretty` is `true` and [`serde_json::to_writer`] otherwise
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndentedJson {
+    indent: String,
+}
+
+impl IndentedJson {
+    /// Creates a format that indents pretty output with `indent`
+    pub fn new(indent: &str) -> Self {
+        Self {
+            indent: indent.to_owned(),
+        }
+    }
+}
+
+impl Format for IndentedJson {
+    type Error = serde_json::Error;
+
+    fn serialize<W, T>(&self, writer: W, value: &T, pretty: bool) -> Result<(), Self::Error>
+    where
+        W: Write,
+        T: Serialize + ?Sized,
+    {
+        if pretty {
+            let formatter = serde_json::ser::PrettyFormatter::with_indent(self.indent.as_bytes());
+            value.serialize(&mut serde_json::Serializer::with_formatter(
+                writer, formatter,
+            ))
+        } else {
+            serde_json::to_writer(writer, value)
+        }
+    }
+
+    fn deserialize<R, T>(&self, reader: R) -> Result<T, Self::Error>
+    where
+        R: Read,
+        T: DeserializeOwned,
+    {
+        serde_json::from_reader(reader)
+    }
+}
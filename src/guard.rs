@@ -0,0 +1,229 @@
+//! Mutable access to the data of a [`FileSync`] that is written to the file when done
+
+use crate::lock::LockGuard;
+use crate::{FileSync, FileSyncError, Format, Json};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Mutable access to the data of a [`FileSync`], returned by [`FileSync::borrow_mut`]
+///
+/// The changes are written to the file (depending on [`FileSync::write_policy`]) by [`FileSyncGuard::commit`], or when the guard is dropped.
+/// Errors while writing on drop are ignored, so use [`FileSyncGuard::commit`] to handle them
+///
+/// If the write fails the data is restored to its value from before the guard was created.
+/// If the thread panics while the guard is alive nothing is written. Changes that would have been written right away are undone as well,
+/// but other changes aren't snapshotted, so they are kept and the [`FileSync`] is poisoned instead. See [`FileSync::is_poisoned`]
+pub struct FileSyncGuard<'a, T, Fmt = Json>
+where
+    T: Serialize + DeserializeOwned,
+    Fmt: Format,
+{
+    file_sync: &'a mut FileSync<T, Fmt>,
+    /// The serialized data from before the guard was created, to roll back to if writing or the caller fails
+    snapshot: Option<Vec<u8>>,
+    /// `true` if the changes will be written right away
+    write: bool,
+    /// The data from before the guard was created, to add to the undo history once the changes are committed
+    history_entry: Option<serde_json::Value>,
+    committed: bool,
+    _lock: Option<LockGuard>,
+}
+
+/// Changes taken out of a [`FileSyncGuard`] by [`FileSyncGuard::into_pending`] that still have to be written to the file
+pub(crate) struct PendingWrite {
+    /// The serialized changes to write
+    pub(crate) bytes: Vec<u8>,
+    /// The serialized data from before the changes, to roll back to if writing fails
+    pub(crate) snapshot: Vec<u8>,
+    /// The data from before the changes, to add to the undo history once they are written
+    pub(crate) history_entry: Option<serde_json::Value>,
+    _lock: Option<LockGuard>,
+}
+
+impl<'a, T, Fmt> FileSyncGuard<'a, T, Fmt>
+where
+    T: Serialize + DeserializeOwned,
+    Fmt: Format,
+{
+    /// Takes a snapshot to roll back to if the changes will be written right away, or always if `always_snapshot` is `true`
+    ///
+    /// With `record_history` the changes are added to the undo history when they are committed, if [`FileSync::set_history`] turned it on
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if [`Format::serialize`] returns an error
+    /// Returns an error if the file was changed by something else, depending on [`FileSync::on_external_change`]
+    pub(crate) fn new(
+        file_sync: &'a mut FileSync<T, Fmt>,
+        always_snapshot: bool,
+        record_history: bool,
+    ) -> Result<Self, FileSyncError> {
+        let mut guard = Self::without_history(file_sync, always_snapshot)?;
+        if record_history && guard.file_sync.history.is_enabled() {
+            guard.history_entry = Some(guard.file_sync.history_entry()?);
+        }
+        Ok(guard)
+    }
+
+    /// # Errors
+    ///
+    /// Returns an error if [`Format::serialize`] returns an error
+    /// Returns an error if the file was changed by something else, depending on [`FileSync::on_external_change`]
+    fn without_history(
+        file_sync: &'a mut FileSync<T, Fmt>,
+        always_snapshot: bool,
+    ) -> Result<Self, FileSyncError> {
+        file_sync.prepare_merge_base()?;
+        if !file_sync.should_write() {
+            let snapshot = if always_snapshot {
+                Some(file_sync.serialize()?)
+            } else {
+                None
+            };
+            return Ok(Self {
+                file_sync,
+                snapshot,
+                write: false,
+                history_entry: None,
+                committed: false,
+                _lock: None,
+            });
+        }
+        let lock = file_sync.write_lock()?;
+        file_sync.handle_external_change()?;
+        let snapshot = file_sync.serialize()?;
+        Ok(Self {
+            file_sync,
+            snapshot: Some(snapshot),
+            write: true,
+            history_entry: None,
+            committed: false,
+            _lock: lock,
+        })
+    }
+
+    /// Writes the changes to the file, depending on [`FileSync::write_policy`]
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if [`Format::serialize`] returns an error
+    /// Returns an error if it fails to write the file
+    /// Returns [`FileSyncError::RollbackFailed`] if the write failed and the previous state couldn't be restored
+    pub fn commit(mut self) -> Result<(), FileSyncError> {
+        self.finish()
+    }
+
+    /// Restores the data from the snapshot instead of committing, returning the error that should be given to the caller in place of `error`
+    ///
+    /// Only restores anything if there is a snapshot, which is guaranteed when the guard was created with `always_snapshot`
+    pub(crate) fn discard(mut self, error: FileSyncError) -> FileSyncError {
+        self.committed = true;
+        match &self.snapshot {
+            Some(snapshot) => self.file_sync.rollback(error, snapshot),
+            None => error,
+        }
+    }
+
+    /// Serializes the changes instead of writing them, so the file can be written without borrowing the [`FileSync`]. See [`PendingWrite`]
+    ///
+    /// Returns `None` if the changes were committed as usual instead, because they aren't written right away, or are written to the journal or in place,
+    /// or the [`FileSync`] is poisoned so committing fails
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if [`Format::serialize`] returns an error, or committing returns an error
+    pub(crate) fn into_pending(mut self) -> Result<Option<PendingWrite>, FileSyncError> {
+        let splittable = self.write
+            && self.file_sync.atomic
+            && self.file_sync.journal.is_none()
+            && !self.file_sync.poisoned;
+        let snapshot = match self.snapshot.take() {
+            Some(snapshot) if splittable => snapshot,
+            snapshot => {
+                self.snapshot = snapshot;
+                return self.finish().map(|()| None);
+            }
+        };
+        self.committed = true;
+        match self.file_sync.serialize() {
+            Ok(bytes) => Ok(Some(PendingWrite {
+                bytes,
+                snapshot,
+                history_entry: self.history_entry.take(),
+                _lock: self._lock.take(),
+            })),
+            Err(e) => Err(self.file_sync.rollback(e, &snapshot)),
+        }
+    }
+
+    /// # Errors
+    ///
+    /// Returns an error if writing the changes fails
+    fn finish(&mut self) -> Result<(), FileSyncError> {
+        self.committed = true;
+        match &self.snapshot {
+            Some(snapshot) if self.write => self
+                .file_sync
+                .sync()
+                .map_err(|error| self.file_sync.rollback(error, snapshot))?,
+            _ => {
+                self.file_sync.dirty = true;
+                self.file_sync.pending = self.file_sync.pending.saturating_add(1);
+            }
+        }
+        if let Some(entry) = self.history_entry.take() {
+            self.file_sync.record_history(entry);
+        }
+        Ok(())
+    }
+}
+
+impl<T, Fmt> Drop for FileSyncGuard<'_, T, Fmt>
+where
+    T: Serialize + DeserializeOwned,
+    Fmt: Format,
+{
+    fn drop(&mut self) {
+        if self.committed {
+            return;
+        }
+        if std::thread::panicking() {
+            // The changes were cut short by a panic, so they are undone instead of written
+            self.committed = true;
+            match self
+                .snapshot
+                .as_deref()
+                .and_then(|snapshot| self.file_sync.format.deserialize(snapshot).ok())
+            {
+                Some(data) => self.file_sync.data = data,
+                None => {
+                    self.file_sync.dirty = true;
+                    self.file_sync.poisoned = true;
+                }
+            }
+        } else {
+            // There's no way to report an error from here, which is why commit exists
+            let _ = self.finish();
+        }
+    }
+}
+
+impl<T, Fmt> std::ops::Deref for FileSyncGuard<'_, T, Fmt>
+where
+    T: Serialize + DeserializeOwned,
+    Fmt: Format,
+{
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.file_sync.data
+    }
+}
+
+impl<T, Fmt> std::ops::DerefMut for FileSyncGuard<'_, T, Fmt>
+where
+    T: Serialize + DeserializeOwned,
+    Fmt: Format,
+{
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.file_sync.data
+    }
+}
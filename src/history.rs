@@ -0,0 +1,181 @@
+//! Undo and redo for a [`FileSync`]
+
+use crate::{Context, FileSync, FileSyncError, FileSyncGuard, Format, Operation};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::path::PathBuf;
+
+/// Past and undone states of the data, stored as [`serde_json::Value`]s
+#[derive(Debug, Default)]
+pub(crate) struct History {
+    depth: usize,
+    persist: bool,
+    undo: VecDeque<Value>,
+    redo: Vec<Value>,
+}
+
+impl History {
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.depth > 0
+    }
+
+    fn push_undo(&mut self, entry: Value) {
+        self.undo.push_back(entry);
+        while self.undo.len() > self.depth {
+            self.undo.pop_front();
+        }
+    }
+}
+
+impl<T, Fmt> FileSync<T, Fmt>
+where
+    T: Serialize + DeserializeOwned,
+    Fmt: Format,
+{
+    /// Starts keeping up to `depth` past states of the data for [`FileSync::undo`], or stops if `depth` is 0
+    ///
+    /// With `persist`, the history is kept in `<file>.history` so it survives restarts, and any history already there is loaded.
+    /// Writing the history file is best effort, since the change it records has already been written by then. If it fails, the history is still kept in memory and the file catches up on the next successful write.
+    /// History is stored as JSON no matter the [`Format`], so `T` has to be representable as a [`serde_json::Value`]
+    ///
+    /// # Errors
+    ///
+    /// Will return [`FileSyncError::Plaintext`] if `persist` is `true` and the format encrypts the data, since the history file would hold it in plain text
+    ///
+    /// Will return an error if reading or parsing an existing history file fails
+    pub fn set_history(&mut self, depth: usize, persist: bool) -> Result<(), FileSyncError> {
+        if persist && depth > 0 && self.format.encrypts() {
+            return Err(FileSyncError::Plaintext {
+                fp: self.history_path(),
+            });
+        }
+        self.history = History {
+            depth,
+            persist,
+            ..History::default()
+        };
+        if persist && depth > 0 {
+            let history_path = self.history_path();
+            match std::fs::read(&history_path) {
+                Ok(bytes) => {
+                    let (undo, redo): (VecDeque<Value>, Vec<Value>) =
+                        serde_json::from_slice(&bytes).context(Operation::Load, &history_path)?;
+                    self.history.undo = undo;
+                    self.history.redo = redo;
+                    while self.history.undo.len() > depth {
+                        self.history.undo.pop_front();
+                    }
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(FileSyncError::io(Operation::Load, &history_path, e)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if there is a state to go back to with [`FileSync::undo`]
+    pub fn can_undo(&self) -> bool {
+        !self.history.undo.is_empty()
+    }
+
+    /// Returns `true` if there is an undone state to go forward to with [`FileSync::redo`]
+    pub fn can_redo(&self) -> bool {
+        !self.history.redo.is_empty()
+    }
+
+    /// Goes back to the state from before the last change and writes it to the file the same way [`FileSync::modify`] would
+    ///
+    /// Returns `false` if there was nothing to undo
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the past state can't be deserialized, or writing it fails. The history is left as it was
+    pub fn undo(&mut self) -> Result<bool, FileSyncError> {
+        let Some(entry) = self.history.undo.pop_back() else {
+            return Ok(false);
+        };
+        match self.restore_history_entry(&entry) {
+            Ok(current) => {
+                self.history.redo.push(current);
+                self.save_history();
+                Ok(true)
+            }
+            Err(e) => {
+                self.history.undo.push_back(entry);
+                Err(e)
+            }
+        }
+    }
+
+    /// Goes forward to the state from before the last [`FileSync::undo`] and writes it to the file the same way [`FileSync::modify`] would
+    ///
+    /// Returns `false` if there was nothing to redo
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the undone state can't be deserialized, or writing it fails. The history is left as it was
+    pub fn redo(&mut self) -> Result<bool, FileSyncError> {
+        let Some(entry) = self.history.redo.pop() else {
+            return Ok(false);
+        };
+        match self.restore_history_entry(&entry) {
+            Ok(current) => {
+                self.history.push_undo(current);
+                self.save_history();
+                Ok(true)
+            }
+            Err(e) => {
+                self.history.redo.push(entry);
+                Err(e)
+            }
+        }
+    }
+
+    /// Returns the current state as a history entry
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the data can't be converted to a [`serde_json::Value`]
+    pub(crate) fn history_entry(&self) -> Result<Value, FileSyncError> {
+        serde_json::to_value(&self.data).context(Operation::Write, &self.path)
+    }
+
+    /// Adds the state from before a change to the undo history, and forgets everything that could be redone
+    pub(crate) fn record_history(&mut self, entry: Value) {
+        self.history.push_undo(entry);
+        self.history.redo.clear();
+        self.save_history();
+    }
+
+    /// Replaces the stored data with `entry` without recording history, returning the state it replaced
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if `entry` can't be deserialized or writing it fails
+    fn restore_history_entry(&mut self, entry: &Value) -> Result<Value, FileSyncError> {
+        let data = T::deserialize(entry).context(Operation::Load, &self.history_path())?;
+        let current = self.history_entry()?;
+        let mut guard = FileSyncGuard::new(self, false, false)?;
+        *guard = data;
+        guard.commit()?;
+        Ok(current)
+    }
+
+    /// Writes the history to `<file>.history` if it is persisted
+    ///
+    /// Errors are ignored, since this happens after the change was written and returning one would break the promise that an error means nothing changed
+    fn save_history(&self) {
+        if self.history.persist {
+            if let Ok(bytes) = serde_json::to_vec(&(&self.history.undo, &self.history.redo)) {
+                let _ = Self::write_atomic(&self.history_path(), &bytes);
+            }
+        }
+    }
+
+    fn history_path(&self) -> PathBuf {
+        let mut name = self.path.file_name().unwrap_or_default().to_os_string();
+        name.push(".history");
+        self.path.with_file_name(name)
+    }
+}
@@ -0,0 +1,406 @@
+//! Journaled storage, where changes are appended to a log instead of rewriting the whole file
+//!
+//! With [`FileSync::set_journal`] the file becomes a snapshot, and each write appends the [JSON Patch](crate::patch) from the last written state to the new one to `<file>.journal`.
+//! Once the journal grows past a size threshold, the whole data is written to the snapshot and the journal starts over. This is called compaction
+//!
+//! The first line of the journal records a hash of the snapshot it applies to, and every other line is one patch.
+//! Loading a file replays the patches in a journal that matches it, whether or not journaling is turned on, so a snapshot written by compaction or a normal write is never patched with an old journal.
+//! A line cut short by a crash in the middle of an append is ignored
+//!
+//! Patches work on [`serde_json::Value`], so `T` has to be representable as one. Changes made to the journal by something else aren't noticed by [`FileSync::is_stale`]
+
+use crate::patch::{self, PatchOperation};
+use crate::{Context, FileStamp, FileSync, FileSyncError, Format, Operation};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// The state of journaling for a [`FileSync`]
+#[derive(Debug)]
+pub(crate) struct Journal {
+    /// Compact once the journal would grow past this many bytes
+    threshold: u64,
+    /// The data as of the last write, which the next patch is diffed against
+    base: Value,
+    /// The journal opened for appending, or `None` if the next write has to compact
+    log: Option<File>,
+    len: u64,
+}
+
+/// The patches of a journal that applies to the current snapshot, and the length of the journal up to the last complete line
+struct JournalContents {
+    patches: Vec<Vec<PatchOperation>>,
+    len: u64,
+}
+
+impl<T, Fmt> FileSync<T, Fmt>
+where
+    T: Serialize + DeserializeOwned,
+    Fmt: Format,
+{
+    /// Turns on journaled storage, compacting once the journal would grow past `compact_after` bytes, or turns it off if `compact_after` is `None`. See the [module level docs](crate::journal)
+    ///
+    /// Nothing is written until the next write. If the existing journal doesn't match the stored data, for example because there are changes that haven't been written yet, the next write compacts
+    ///
+    /// # Errors
+    ///
+    /// Will return [`FileSyncError::Plaintext`] if the format encrypts the data, since the journal would hold it in plain text
+    ///
+    /// Will return an error if the data can't be converted to a [`serde_json::Value`]
+    ///
+    /// Will return an error if reading or opening the existing journal fails
+    pub fn set_journal(&mut self, compact_after: Option<u64>) -> Result<(), FileSyncError> {
+        let Some(threshold) = compact_after else {
+            self.journal = None;
+            return Ok(());
+        };
+        if self.format.encrypts() {
+            return Err(FileSyncError::Plaintext {
+                fp: Self::journal_path(&self.path),
+            });
+        }
+        let base = serde_json::to_value(&self.data).context(Operation::Write, &self.path)?;
+        let journal_path = Self::journal_path(&self.path);
+        let contents = if self.dirty {
+            None
+        } else {
+            Self::read_journal(&journal_path, self.stamp.hash)?
+        };
+        let (log, len) = match contents {
+            Some(contents) => {
+                let log = File::options()
+                    .append(true)
+                    .open(&journal_path)
+                    .context(Operation::Write, &journal_path)?;
+                // Drop a line cut short by a crash so the next one doesn't get appended to it
+                log.set_len(contents.len)
+                    .context(Operation::Write, &journal_path)?;
+                (Some(log), contents.len)
+            }
+            None => (None, 0),
+        };
+        self.journal = Some(Journal {
+            threshold,
+            base,
+            log,
+            len,
+        });
+        Ok(())
+    }
+
+    /// Starts journaling from the current state again after the stored data was read from the file, if journaling is on
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if [`FileSync::set_journal`] returns an error
+    pub(crate) fn reopen_journal(&mut self) -> Result<(), FileSyncError> {
+        match self.journal.as_ref().map(|journal| journal.threshold) {
+            Some(threshold) => self.set_journal(Some(threshold)),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns `true` if [`FileSync::set_journal`] turned on journaled storage
+    pub fn is_journaled(&self) -> bool {
+        self.journal.is_some()
+    }
+
+    /// Writes the stored data by appending a patch to the journal, or by compacting if the journal would grow too big
+    ///
+    /// Either way, nothing on disk changes if this returns an error
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the data can't be converted to a [`serde_json::Value`]
+    ///
+    /// Will return an error if writing the journal or the snapshot fails
+    pub(crate) fn sync_journal(&mut self) -> Result<(), FileSyncError> {
+        let value = serde_json::to_value(&self.data).context(Operation::Write, &self.path)?;
+        let journal_path = Self::journal_path(&self.path);
+        let Some(journal) = &mut self.journal else {
+            return Ok(());
+        };
+        let ops = patch::diff(&journal.base, &value);
+        if ops.is_empty() && journal.log.is_some() {
+            return Ok(());
+        }
+        let mut line = serde_json::to_vec(&ops).context(Operation::Write, &journal_path)?;
+        line.push(b'\n');
+        let new_len = journal
+            .len
+            .saturating_add(u64::try_from(line.len()).unwrap_or(u64::MAX));
+        match &mut journal.log {
+            Some(log) if new_len <= journal.threshold => {
+                if let Err(e) = log.write_all(&line).and_then(|()| log.sync_data()) {
+                    // Best effort, a partial line is ignored on load anyway
+                    let _ = log.set_len(journal.len);
+                    return Err(FileSyncError::io(Operation::Write, &journal_path, e));
+                }
+                journal.len = new_len;
+                journal.base = value;
+                Ok(())
+            }
+            _ => self.compact(value),
+        }
+    }
+
+    /// Writes the stored data, whose [`serde_json::Value`] is `value`, to the snapshot and starts a new journal
+    ///
+    /// The snapshot is always written atomically, so a failed compaction leaves the old snapshot and journal intact
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if [`Format::serialize`] returns an error, or backing up or writing the snapshot fails
+    fn compact(&mut self, value: Value) -> Result<(), FileSyncError> {
+        let bytes = self.serialize()?;
+        self.back_up()?;
+        self.file = Self::write_atomic(&self.path, &bytes).context(Operation::Write, &self.path)?;
+        let metadata = self.file.metadata().context(Operation::Write, &self.path)?;
+        self.stamp = FileStamp::new(&metadata, &bytes);
+        // The snapshot now holds everything, and the old journal no longer matches it
+        let journal_path = Self::journal_path(&self.path);
+        let log = Self::create_journal(&journal_path, self.stamp.hash);
+        let Some(journal) = &mut self.journal else {
+            return Ok(());
+        };
+        journal.base = value;
+        match log {
+            Ok((log, len)) => {
+                journal.log = Some(log);
+                journal.len = len;
+            }
+            Err(_) => {
+                // The data is safely in the snapshot, so this isn't an error. The next write compacts again
+                let _ = std::fs::remove_file(&journal_path);
+                journal.log = None;
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces the journal at `journal_path` with an empty one for the snapshot hashed to `base`, returning it opened for appending along with its length
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if writing or opening the journal fails
+    fn create_journal(journal_path: &Path, base: u64) -> Result<(File, u64), FileSyncError> {
+        let mut header = serde_json::to_vec(&serde_json::json!({ "base": format!("{base:016x}") }))
+            .context(Operation::Write, journal_path)?;
+        header.push(b'\n');
+        Self::write_atomic(journal_path, &header).context(Operation::Write, journal_path)?;
+        let log = File::options()
+            .append(true)
+            .open(journal_path)
+            .context(Operation::Write, journal_path)?;
+        Ok((log, u64::try_from(header.len()).unwrap_or(u64::MAX)))
+    }
+
+    /// Applies the patches in the journal of the file at `fp` to `data`, if the journal matches the snapshot hashed to `base`
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if reading the journal fails, or a patch in it is invalid or can't be applied
+    pub(crate) fn replay_journal(fp: &Path, data: T, base: u64) -> Result<T, FileSyncError> {
+        let Some(contents) = Self::read_journal(&Self::journal_path(fp), base)? else {
+            return Ok(data);
+        };
+        if contents.patches.is_empty() {
+            return Ok(data);
+        }
+        let mut value = serde_json::to_value(&data).context(Operation::Load, fp)?;
+        for ops in &contents.patches {
+            patch::apply_in_place(&mut value, ops)?;
+        }
+        T::deserialize(value).context(Operation::Load, fp)
+    }
+
+    /// [`FileSync::replay_journal`] for data that is already a [`serde_json::Value`]
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if reading the journal fails, or a patch in it is invalid or can't be applied
+    pub(crate) fn replay_journal_value(
+        fp: &Path,
+        value: &mut Value,
+        base: u64,
+    ) -> Result<(), FileSyncError> {
+        if let Some(contents) = Self::read_journal(&Self::journal_path(fp), base)? {
+            for ops in &contents.patches {
+                patch::apply_in_place(value, ops)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the journal at `journal_path`, returning `None` if it doesn't exist or doesn't match the snapshot hashed to `base`
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if reading the journal fails, or a line other than the last one isn't a valid patch
+    fn read_journal(
+        journal_path: &Path,
+        base: u64,
+    ) -> Result<Option<JournalContents>, FileSyncError> {
+        let bytes = match std::fs::read(journal_path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(FileSyncError::io(Operation::Load, journal_path, e)),
+        };
+        let mut lines = bytes.split_inclusive(|&b| b == b'\n').peekable();
+        let header: Option<Value> = lines
+            .next()
+            .and_then(|line| serde_json::from_slice(line).ok());
+        let expected = format!("{base:016x}");
+        if header
+            .as_ref()
+            .and_then(|header| header.get("base"))
+            .and_then(Value::as_str)
+            != Some(expected.as_str())
+        {
+            return Ok(None);
+        }
+        let mut len = bytes
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(0, |end| end.saturating_add(1));
+        let mut patches = Vec::new();
+        let mut line_number: usize = 1;
+        while let Some(line) = lines.next() {
+            line_number = line_number.saturating_add(1);
+            // The last line can be cut short by a crash while appending it
+            if !line.ends_with(b"\n") {
+                break;
+            }
+            match serde_json::from_slice(line) {
+                Ok(ops) => patches.push(ops),
+                Err(_) if lines.peek().is_none() => break,
+                // serde_json only sees this one line, so the line in the journal is counted here
+                Err(source) => {
+                    return Err(FileSyncError::SerdeJsonError {
+                        op: Operation::Load,
+                        fp: journal_path.to_path_buf(),
+                        line: line_number,
+                        column: source.column(),
+                        source,
+                    })
+                }
+            }
+            len = len.saturating_add(line.len());
+        }
+        Ok(Some(JournalContents {
+            patches,
+            len: u64::try_from(len).unwrap_or(u64::MAX),
+        }))
+    }
+
+    /// Returns the path of the journal of the file at `fp`, `<file>.journal`
+    pub(crate) fn journal_path(fp: &Path) -> PathBuf {
+        let mut name = fp.file_name().unwrap_or_default().to_os_string();
+        name.push(".journal");
+        fp.with_file_name(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const BASE: u64 = 0x1234;
+    const HEADER: &str = "{\"base\":\"0000000000001234\"}\n";
+
+    /// Writes a journal with `contents` for the file `name` in the temp directory, returning the path of the file
+    fn write_journal(name: &str, contents: &str) -> PathBuf {
+        let fp = std::env::temp_dir().join(format!(
+            "file_sync_{}_journal_{name}.json",
+            std::process::id()
+        ));
+        std::fs::write(FileSync::<Value>::journal_path(&fp), contents).unwrap();
+        fp
+    }
+
+    fn replay(fp: &Path, base: u64) -> Result<Value, FileSyncError> {
+        FileSync::<Value>::replay_journal(fp, json!({"a": 1}), base)
+    }
+
+    #[test]
+    fn replays_patches() {
+        let fp = write_journal(
+            "replays_patches",
+            &format!(
+                "{HEADER}{}\n{}\n",
+                r#"[{"op":"replace","path":"/a","value":2}]"#,
+                r#"[{"op":"add","path":"/b","value":3}]"#
+            ),
+        );
+
+        assert_eq!(replay(&fp, BASE).unwrap(), json!({"a": 2, "b": 3}));
+        std::fs::remove_file(FileSync::<Value>::journal_path(&fp)).unwrap();
+    }
+
+    #[test]
+    fn ignores_torn_last_line() {
+        let complete = format!(
+            "{HEADER}{}\n",
+            r#"[{"op":"replace","path":"/a","value":2}]"#
+        );
+        let fp = write_journal(
+            "ignores_torn_last_line",
+            &format!("{complete}{}", r#"[{"op":"add","path":"/b","#),
+        );
+        let journal_path = FileSync::<Value>::journal_path(&fp);
+
+        assert_eq!(replay(&fp, BASE).unwrap(), json!({"a": 2}));
+        let contents = FileSync::<Value>::read_journal(&journal_path, BASE)
+            .unwrap()
+            .unwrap();
+        assert_eq!(contents.len, complete.len() as u64);
+        std::fs::remove_file(journal_path).unwrap();
+    }
+
+    #[test]
+    fn ignores_unparseable_last_line() {
+        let fp = write_journal(
+            "ignores_unparseable_last_line",
+            &format!(
+                "{HEADER}{}\n{}\n",
+                r#"[{"op":"replace","path":"/a","value":2}]"#, r#"[{"op":"add","#
+            ),
+        );
+
+        assert_eq!(replay(&fp, BASE).unwrap(), json!({"a": 2}));
+        std::fs::remove_file(FileSync::<Value>::journal_path(&fp)).unwrap();
+    }
+
+    #[test]
+    fn corrupted_line_is_an_error() {
+        let fp = write_journal(
+            "corrupted_line_is_an_error",
+            &format!(
+                "{HEADER}{}\n{}\n",
+                r#"[{"op":"add","#, r#"[{"op":"replace","path":"/a","value":2}]"#
+            ),
+        );
+
+        let e = replay(&fp, BASE).unwrap_err();
+
+        assert!(matches!(e, FileSyncError::SerdeJsonError { line: 2, .. }));
+        std::fs::remove_file(FileSync::<Value>::journal_path(&fp)).unwrap();
+    }
+
+    #[test]
+    fn ignores_journal_of_other_snapshot() {
+        let fp = write_journal(
+            "ignores_journal_of_other_snapshot",
+            &format!(
+                "{HEADER}{}\n",
+                r#"[{"op":"replace","path":"/a","value":2}]"#
+            ),
+        );
+
+        assert_eq!(replay(&fp, BASE + 1).unwrap(), json!({"a": 1}));
+        std::fs::remove_file(FileSync::<Value>::journal_path(&fp)).unwrap();
+    }
+}
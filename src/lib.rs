@@ -1,4 +1,5 @@
-//! Note: Methods that take a `&mut self` and return a [`Result`] might cause de-sync between the internal data and the file if the [`Result`] is an [`Err`]
+//! Methods that take a `&mut self` and return a [`Result`] restore the internal data and the file to their previous state if the [`Result`] is an [`Err`].
+//! The only exception is [`FileSyncError::RollbackFailed`], which is returned when that restoration itself fails
 
 #![warn(clippy::cast_possible_truncation)]
 #![warn(clippy::exit)]
@@ -6,83 +7,444 @@
 #![warn(clippy::fallible_impl_from)]
 #![cfg_attr(not(test), warn(clippy::index_refutable_slice))]
 #![cfg_attr(not(test), warn(clippy::indexing_slicing))]
-#![cfg_attr(not(test), warn(clippy::integer_arithmetic))]
+#![cfg_attr(not(test), warn(clippy::arithmetic_side_effects))]
 #![cfg_attr(not(test), warn(clippy::missing_panics_doc))]
 #![cfg_attr(not(test), warn(clippy::panic))]
-#![warn(clippy::unchecked_duration_subtraction)]
+#![warn(clippy::unchecked_time_subtraction)]
 #![cfg_attr(not(test), warn(clippy::unreachable))]
 #![cfg_attr(not(test), warn(clippy::unwrap_used))]
 
 use serde::{de::DeserializeOwned, Serialize};
 use std::fs::File;
+use std::io::Write;
+use std::num::NonZeroUsize;
 #[doc(no_inline)]
 pub use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+#[cfg(feature = "tokio")]
+pub mod asynchronous;
+#[cfg(feature = "tokio")]
+pub use asynchronous::AsyncFileSync;
+pub mod backup;
+pub use backup::BackupPolicy;
+pub mod builder;
+pub use builder::{FileSyncBuilder, OnInvalidFile};
+pub mod checksum;
+use checksum::ChecksumError;
+pub use checksum::Checksummed;
+pub mod compression;
+use compression::CompressionError;
+pub use compression::{Codec, Compressed};
+pub mod encryption;
+use encryption::EncryptionError;
+pub use encryption::{Cipher, Encrypted};
+pub mod format;
+pub use format::{Format, IndentedJson, Json};
+pub mod guard;
+pub use guard::FileSyncGuard;
+pub mod history;
+use history::History;
+pub mod journal;
+use journal::Journal;
+pub mod lock;
+use lock::FileLock;
+pub use lock::{LockMode, LockWait, Locking};
+pub mod merge;
+pub use merge::Conflict;
+use merge::Resolver;
+pub mod patch;
+pub use patch::{JsonDiff, PatchOperation};
+pub mod shared;
+pub use shared::{SharedFileSync, SharedReadGuard, SharedWriteGuard};
+pub mod versioning;
+use versioning::{Migration, VersionedError};
+pub use versioning::{Migrations, Versioned};
+pub mod watch;
+pub use watch::{WatchEvent, WatchedFileSync};
 
-/// Note: Methods that take a `&mut self` and return a [`Result`] might cause de-sync between the internal data and the file if the [`Result`] is an [`Err`]
 #[derive(Debug)]
-pub struct FileSync<T>
+pub struct FileSync<T, Fmt = Json>
 where
     T: Serialize + DeserializeOwned,
+    Fmt: Format,
 {
     data: T,
     file: File,
-    /// Specifies if when writing to the file if [`serde_json::to_writer_pretty`] will be used instead of [`serde_json::to_writer`]
+    path: PathBuf,
+    format: Fmt,
+    lock: Option<Arc<FileLock>>,
+    stamp: FileStamp,
+    /// Passed to [`Format::serialize`] when writing to the file. For [`Json`] it specifies if [`serde_json::to_writer_pretty`] will be used instead of [`serde_json::to_writer`]
     pub pretty: bool,
+    /// Specifies if writes go to a temporary file that is then renamed over the synced file, instead of truncating and rewriting the synced file in place
+    ///
+    /// Defaults to `true`. With this on, a crash or an error in the middle of a write leaves the previous contents of the file intact
+    pub atomic: bool,
+    /// What [`FileSync::set`] and [`FileSync::modify`] do when the file was changed by something else since it was last read or written
+    ///
+    /// Defaults to [`ExternalChangePolicy::Overwrite`]
+    pub on_external_change: ExternalChangePolicy,
+    /// When [`FileSync::set`] and [`FileSync::modify`] write their changes to the file
+    ///
+    /// Defaults to [`WritePolicy::Immediate`]
+    pub write_policy: WritePolicy,
+    /// If and how copies of the file from before each write are kept
+    ///
+    /// Defaults to [`BackupPolicy::None`]
+    pub backups: BackupPolicy,
+    history: History,
+    journal: Option<Journal>,
+    /// The data as it was last read from or written to the file, kept for [`ExternalChangePolicy::Merge`]
+    merge_base: Option<serde_json::Value>,
+    merge_resolver: Option<Resolver>,
+    /// `true` if the stored data has changes that haven't been written to the file yet
+    dirty: bool,
+    /// Number of changes that haven't been written to the file yet
+    pending: usize,
+    last_write: Option<Instant>,
+    /// `true` if a change was cut short by a panic and couldn't be undone. See [`FileSync::is_poisoned`]
+    poisoned: bool,
 }
 
-#[derive(thiserror::Error, Debug)]
-pub enum FileSyncError<'a> {
-    #[error("File \"{fp}\" already exists")]
-    FileAlreadyExists { fp: &'a Path },
-    #[error("IO error")]
-    IoError(#[from] std::io::Error),
-    #[error("serde_json error")]
-    SerdeJsonError(#[from] serde_json::Error),
-    #[error("ClearFile error")]
-    ClearFileError(#[from] ClearFileError),
+/// How long [`FileSync::load_or_new`] waits for a file that another process created at the same time to be written
+const CREATE_WAIT: Duration = Duration::from_secs(1);
+
+/// The result of [`FileSync::read_or_create`]
+pub(crate) enum Opened<T> {
+    /// The contents of the existing file, as returned by [`FileSync::read_file`]
+    Existing((File, T, FileStamp)),
+    /// The newly created, empty file
+    Created(File),
+}
+
+/// When a [`FileSync`] writes changes to its file
+///
+/// With anything but [`WritePolicy::Immediate`], changes that haven't been written yet are written by [`FileSync::flush`] or when the `FileSync` is dropped.
+/// Errors while writing on drop are ignored, so call [`FileSync::flush`] before dropping to handle them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WritePolicy {
+    /// Write on every change
+    #[default]
+    Immediate,
+    /// Write at most once per duration
+    ///
+    /// This is a throttle, not a debounce. A change made before the duration has passed since the last write is held until the next change after it has passed,
+    /// so the last change of a burst isn't written until something else changes. Nothing writes it in the background, so call [`FileSync::flush`] to write it sooner, for example from a timer
+    Throttle(Duration),
+    /// Write on every nth change
+    EveryN(NonZeroUsize),
+    /// Only write on [`FileSync::flush`]
+    Manual,
+}
+
+/// What to do when writing to a file that was changed by something else since a [`FileSync`] last read or wrote it. See [`FileSync::is_stale`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExternalChangePolicy {
+    /// Overwrite the file without checking it
+    #[default]
+    Overwrite,
+    /// Return [`FileSyncError::ModifiedExternally`] without changing anything
+    Error,
+    /// Read the file with [`FileSync::reload`] before applying the change
+    ///
+    /// If there are changes that haven't been written yet because of [`FileSync::write_policy`], [`FileSyncError::ModifiedExternally`] is returned instead so they aren't lost
+    Reload,
+    /// Merge the changes in the file into the stored data before applying the change, using the data as it was last read or written as the base. See the [`merge`] module
+    ///
+    /// Conflicts are resolved by the function given to [`FileSync::set_merge_resolver`], or returned as [`FileSyncError::MergeConflict`]
+    Merge,
+}
+
+/// What the file looked like when it was last read or written, used to notice changes made by something else
+#[derive(Debug, Default, PartialEq, Eq)]
+struct FileStamp {
+    len: u64,
+    modified: Option<SystemTime>,
+    hash: u64,
+}
+
+impl FileStamp {
+    fn new(metadata: &std::fs::Metadata, bytes: &[u8]) -> Self {
+        Self {
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+            hash: Self::hash(bytes),
+        }
+    }
+
+    /// FNV-1a, which unlike [`std::hash::DefaultHasher`] is stable across Rust versions, since the hash is stored in the [`journal`]
+    fn hash(bytes: &[u8]) -> u64 {
+        bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+            (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+        })
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
-pub enum ClearFileError {
-    #[error("Failed to set len of file to 0. Extra info: {0}")]
-    SetLenError(std::io::Error),
-    #[error("Failed to seek to beginning of file. Extra info: {0}")]
-    SeekError(std::io::Error),
+pub enum FileSyncError {
+    #[error("File \"{}\" already exists", fp.display())]
+    FileAlreadyExists { fp: PathBuf },
+    /// Doing `op` on the file at `fp` failed
+    #[error("Failed to {op} \"{}\"", fp.display())]
+    IoError {
+        op: Operation,
+        fp: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    /// [`serde_json`] failed while doing `op` on the file at `fp`. `line` and `column` are where in the file it failed, or 0 if the error didn't come from reading it
+    #[error("Failed to {op} \"{}\"{}", fp.display(), location(*line, *column))]
+    SerdeJsonError {
+        op: Operation,
+        fp: PathBuf,
+        line: usize,
+        column: usize,
+        #[source]
+        source: serde_json::Error,
+    },
+    /// The lock file at `lock_fp` is held by another process. See [`FileSync::set_locking`]
+    #[error("File \"{}\" is locked by another process", lock_fp.display())]
+    Locked { lock_fp: PathBuf },
+    /// The file at `fp` was changed by something else since it was last read or written. See [`ExternalChangePolicy::Error`]
+    #[error("File \"{}\" was modified by something else since it was last synced", fp.display())]
+    ModifiedExternally { fp: PathBuf },
+    /// Merging the changes in the file at `fp` ran into `conflicts`. See [`ExternalChangePolicy::Merge`]
+    #[error("Merging changes to \"{}\" ran into {} conflicts", fp.display(), conflicts.len())]
+    MergeConflict {
+        fp: PathBuf,
+        conflicts: Vec<Conflict>,
+    },
+    /// The checksum of the file at `fp` didn't match its contents, so it is probably corrupted. See [`Checksummed`]
+    #[error("Checksum of \"{}\" is {actual:08x}, but {expected:08x} was stored", fp.display())]
+    ChecksumMismatch {
+        op: Operation,
+        fp: PathBuf,
+        expected: u32,
+        actual: u32,
+    },
+    /// The file at `fp` was encrypted with a different key than the one given to [`Encrypted`]
+    #[error("File \"{}\" was encrypted with a different key", fp.display())]
+    WrongKey { op: Operation, fp: PathBuf },
+    /// The file at `fp` failed to decrypt with the right key, so it was tampered with or corrupted. See [`Encrypted`]
+    #[error("Failed to decrypt \"{}\", it was tampered with or corrupted", fp.display())]
+    DecryptionFailed {
+        op: Operation,
+        fp: PathBuf,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    /// Turning on the journal or a persisted history would write the data of an encrypted file to `fp` in plain text. See [`Encrypted`]
+    #[error("Refusing to write the data of an encrypted file to \"{}\" in plain text", fp.display())]
+    Plaintext { fp: PathBuf },
+    /// A change to the data of the file at `fp` was cut short by a panic, so the data isn't written. See [`FileSync::is_poisoned`]
+    #[error("A change to \"{}\" was cut short by a panic", fp.display())]
+    Poisoned { fp: PathBuf },
+    /// [`FileSync::restore_backup`] was asked for backup `n`, which doesn't exist
+    #[error("Backup {n} doesn't exist")]
+    BackupNotFound { n: usize },
+    /// The closure passed to [`FileSync::try_modify`] returned an error
+    #[error("Closure returned an error")]
+    ClosureError(#[source] Box<dyn std::error::Error + Send + Sync>),
+    /// An error from a [`Format`] other than [`Json`] while doing `op` on the file at `fp`
+    #[error("Failed to {op} \"{}\"", fp.display())]
+    FormatError {
+        op: Operation,
+        fp: PathBuf,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    #[error("JSON Patch error")]
+    PatchError(#[from] patch::PatchError),
+    /// Writing failed with `error`, and then restoring the previous state failed with `rollback_error`
+    ///
+    /// When this is returned the internal data and the file might not match
+    #[error("Failed to roll back after \"{error}\": {rollback_error}")]
+    RollbackFailed {
+        error: Box<FileSyncError>,
+        rollback_error: Box<FileSyncError>,
+    },
+}
+
+impl FileSyncError {
+    fn io(op: Operation, fp: &Path, source: std::io::Error) -> Self {
+        FileSyncError::IoError {
+            op,
+            fp: fp.to_path_buf(),
+            source,
+        }
+    }
+
+    fn format(op: Operation, fp: &Path, source: Box<dyn std::error::Error + Send + Sync>) -> Self {
+        FileSyncError::FormatError {
+            op,
+            fp: fp.to_path_buf(),
+            source,
+        }
+    }
+
+    fn serde_json(op: Operation, fp: &Path, source: serde_json::Error) -> Self {
+        FileSyncError::SerdeJsonError {
+            op,
+            fp: fp.to_path_buf(),
+            line: source.line(),
+            column: source.column(),
+            source,
+        }
+    }
+
+    /// Wraps an error returned by a [`Format`] while doing `op` on the file at `fp`, keeping [`serde_json::Error`]s in [`FileSyncError::SerdeJsonError`] and checksum mismatches in [`FileSyncError::ChecksumMismatch`]
+    ///
+    /// The errors of [`Checksummed`], [`Compressed`], [`Encrypted`] and [`Versioned`] are unwrapped to find the error of the format inside them
+    fn from_format<E>(e: E, op: Operation, fp: &Path) -> Self
+    where
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        Self::from_boxed_format(Box::new(e), op, fp)
+    }
+
+    fn from_boxed_format(
+        boxed: Box<dyn std::error::Error + Send + Sync>,
+        op: Operation,
+        fp: &Path,
+    ) -> Self {
+        let boxed = match boxed.downcast::<serde_json::Error>() {
+            Ok(e) => return Self::serde_json(op, fp, *e),
+            Err(boxed) => boxed,
+        };
+        let boxed = match boxed.downcast::<ChecksumError>() {
+            Ok(e) => {
+                return match *e {
+                    ChecksumError::Mismatch { expected, actual } => {
+                        FileSyncError::ChecksumMismatch {
+                            op,
+                            fp: fp.to_path_buf(),
+                            expected,
+                            actual,
+                        }
+                    }
+                    ChecksumError::Format(inner) => Self::from_boxed_format(inner, op, fp),
+                    e => Self::format(op, fp, Box::new(e)),
+                }
+            }
+            Err(boxed) => boxed,
+        };
+        let boxed = match boxed.downcast::<CompressionError>() {
+            Ok(e) => {
+                return match *e {
+                    CompressionError::Format(inner) => Self::from_boxed_format(inner, op, fp),
+                    CompressionError::Io(e) => Self::io(op, fp, e),
+                }
+            }
+            Err(boxed) => boxed,
+        };
+        let boxed = match boxed.downcast::<EncryptionError>() {
+            Ok(e) => {
+                return match *e {
+                    EncryptionError::WrongKey => FileSyncError::WrongKey {
+                        op,
+                        fp: fp.to_path_buf(),
+                    },
+                    EncryptionError::Decrypt(source) => FileSyncError::DecryptionFailed {
+                        op,
+                        fp: fp.to_path_buf(),
+                        source,
+                    },
+                    EncryptionError::Format(inner) => Self::from_boxed_format(inner, op, fp),
+                    e => Self::format(op, fp, Box::new(e)),
+                }
+            }
+            Err(boxed) => boxed,
+        };
+        // Only a Versioned format wrapping Json can be recognized, since the error type is generic over the inner format
+        match boxed.downcast::<VersionedError<serde_json::Error>>() {
+            Ok(e) => match *e {
+                VersionedError::Format(e) => Self::serde_json(op, fp, e),
+                e => Self::format(op, fp, Box::new(e)),
+            },
+            Err(boxed) => Self::format(op, fp, boxed),
+        }
+    }
+}
+
+/// Formats where in a file a [`FileSyncError::SerdeJsonError`] happened, or nothing if it didn't come from reading a file
+fn location(line: usize, column: usize) -> String {
+    if line == 0 {
+        String::new()
+    } else {
+        format!(" at line {line}, column {column}")
+    }
+}
+
+/// What was being done with a file when a [`FileSyncError`] happened
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    /// Reading the file or one of the files kept next to it, like its journal or backups, or converting what was read
+    Load,
+    /// Creating the file or its parent directories
+    Create,
+    /// Writing the file or one of the files kept next to it, or converting the stored data to write it
+    Write,
+    /// Truncating the file before writing it in place, or deleting it
+    Clear,
+    /// Opening or locking the lock file. See [`FileSync::set_locking`]
+    Lock,
+}
+
+impl std::fmt::Display for Operation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Operation::Load => "load",
+            Operation::Create => "create",
+            Operation::Write => "write",
+            Operation::Clear => "clear",
+            Operation::Lock => "lock",
+        })
+    }
+}
+
+/// Attaches the [`Operation`] and path to IO and [`serde_json`] errors
+pub(crate) trait Context<T> {
+    fn context(self, op: Operation, fp: &Path) -> Result<T, FileSyncError>;
+}
+
+impl<T> Context<T> for std::io::Result<T> {
+    fn context(self, op: Operation, fp: &Path) -> Result<T, FileSyncError> {
+        self.map_err(|e| FileSyncError::io(op, fp, e))
+    }
+}
+
+impl<T> Context<T> for Result<T, serde_json::Error> {
+    fn context(self, op: Operation, fp: &Path) -> Result<T, FileSyncError> {
+        self.map_err(|e| FileSyncError::serde_json(op, fp, e))
+    }
 }
 
 impl<T> FileSync<T>
 where
     T: Serialize + DeserializeOwned,
 {
-    /// Creates a new `FileSync` type syncing a file with the path `fp` and `data`
+    /// Creates a new `FileSync` type syncing a file with the path `fp` and `data`, stored as [`Json`]
     ///
     /// `pretty` determines if it will use [`serde_json::to_writer_pretty`] instead of [`serde_json::to_writer`]
     ///
     /// # Errors
     ///
-    /// Will return an error if a file at that path already exists
+    /// Will return [`FileSyncError::FileAlreadyExists`] if a file at that path already exists, including one created by another process in the meantime
     ///
     /// Will return an error if the creating the [`File`] returns an error
     ///
     /// Will return an error if [`serde_json::to_writer`]/[`serde_json::to_writer_pretty`] returns an error
     pub fn new(fp: &Path, data: T, pretty: bool) -> Result<Self, FileSyncError> {
-        if fp.exists() {
-            Err(FileSyncError::FileAlreadyExists { fp })
-        } else {
-            let file = File::options()
-                .write(true)
-                .read(true)
-                .create(true)
-                .truncate(true)
-                .open(fp)?;
-            Self::write(&file, &data, pretty)?;
-            Ok(Self { data, file, pretty })
-        }
+        Self::new_with_format(fp, data, pretty, Json)
     }
 
-    /// Creates a new `FileSync` type loading and syncing data from an already existing file
+    /// Creates a new `FileSync` type loading and syncing data from an already existing [`Json`] file
     ///
-    /// `pretty` determines if iet will use [`serde_json::to_writer_pretty`] instead of [`serde_json::to_writer`]
+    /// `pretty` determines if it will use [`serde_json::to_writer_pretty`] instead of [`serde_json::to_writer`]
     ///
     /// # Errors
     ///
@@ -90,14 +452,14 @@ where
     ///
     /// Will return an error if [`serde_json::from_reader`] returns an error
     pub fn load(fp: &Path, pretty: bool) -> Result<Self, FileSyncError> {
-        let file = File::options().read(true).write(true).open(fp)?;
-        let data = serde_json::from_reader(&file)?;
-        Ok(Self { data, file, pretty })
+        Self::load_with_format(fp, pretty, Json)
     }
 
-    /// Creates a new `FileSync` type loading and syncing data from an already existing file, or creating a new one if the file doesn't exist
+    /// Creates a new `FileSync` type loading and syncing data from an already existing [`Json`] file, or creating a new one if the file doesn't exist
     ///
-    /// `pretty` determines if iet will use serde_json::to_writer_pretty instead of [`serde_json::to_writer`]
+    /// If several processes call this at the same time and the file doesn't exist, exactly one of them creates it and the others load its data
+    ///
+    /// `pretty` determines if it will use [`serde_json::to_writer_pretty`] instead of [`serde_json::to_writer`]
     ///
     /// # Errors
     ///
@@ -107,10 +469,288 @@ where
     ///
     /// Will return an error if [`serde_json::from_reader`] returns an error
     pub fn load_or_new(fp: &Path, data: T, pretty: bool) -> Result<Self, FileSyncError> {
-        if fp.exists() {
-            FileSync::load(fp, pretty)
-        } else {
-            FileSync::new(fp, data, pretty)
+        Self::load_or_new_with_format(fp, data, pretty, Json)
+    }
+
+    /// [`FileSync::load_or_new`], but the data for a new file is returned by `default`, which is only called if the file doesn't exist
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if [`FileSync::load_or_new`] would
+    pub fn load_or_else<F>(fp: &Path, pretty: bool, default: F) -> Result<Self, FileSyncError>
+    where
+        F: FnOnce() -> T,
+    {
+        Self::load_or_else_with_format(fp, pretty, Json, default)
+    }
+
+    /// [`FileSync::load_or_new`] with [`Default::default`] as the data for a new file
+    ///
+    /// To also fall back to the default when the existing file can't be deserialized, use [`FileSync::builder`] with [`FileSyncBuilder::on_invalid`]
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if [`FileSync::load_or_new`] would
+    pub fn load_or_default(fp: &Path, pretty: bool) -> Result<Self, FileSyncError>
+    where
+        T: Default,
+    {
+        Self::load_or_else(fp, pretty, T::default)
+    }
+}
+
+impl<T, Fmt> FileSync<T, Fmt>
+where
+    T: Serialize + DeserializeOwned,
+    Fmt: Format,
+{
+    /// Creates a new `FileSync` type syncing a file with the path `fp` and `data`, stored as `format`
+    ///
+    /// `pretty` is passed to [`Format::serialize`]
+    ///
+    /// # Errors
+    ///
+    /// Will return [`FileSyncError::FileAlreadyExists`] if a file at that path already exists, including one created by another process in the meantime
+    ///
+    /// Will return an error if the creating the [`File`] returns an error
+    ///
+    /// Will return an error if [`Format::serialize`] returns an error
+    pub fn new_with_format(
+        fp: &Path,
+        data: T,
+        pretty: bool,
+        format: Fmt,
+    ) -> Result<Self, FileSyncError> {
+        Self::create_with(fp, data, pretty, format, None)
+    }
+
+    /// [`FileSync::new_with_format`], giving the file `permissions` before anything is written to it
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if [`FileSync::new_with_format`] would, or setting the permissions fails
+    fn create_with(
+        fp: &Path,
+        data: T,
+        pretty: bool,
+        format: Fmt,
+        permissions: Option<std::fs::Permissions>,
+    ) -> Result<Self, FileSyncError> {
+        let file = match Self::create_file(fp, permissions) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                return Err(FileSyncError::FileAlreadyExists {
+                    fp: fp.to_path_buf(),
+                })
+            }
+            Err(e) => return Err(FileSyncError::io(Operation::Create, fp, e)),
+        };
+        Self::from_new_file(fp, file, data, pretty, format)
+    }
+
+    /// Creates the file at `fp` with `permissions`, failing if it already exists
+    ///
+    /// # Errors
+    ///
+    /// Will return an error with [`std::io::ErrorKind::AlreadyExists`] if the file already exists
+    ///
+    /// Will return an error if creating the file or setting its permissions fails
+    fn create_file(fp: &Path, permissions: Option<std::fs::Permissions>) -> std::io::Result<File> {
+        // create_new checks and creates in one step, so two processes can't both create the file
+        let file = File::options()
+            .write(true)
+            .read(true)
+            .create_new(true)
+            .open(fp)?;
+        if let Some(permissions) = permissions {
+            file.set_permissions(permissions)?;
+        }
+        Ok(file)
+    }
+
+    /// Creates a `FileSync` type for the newly created, empty `file` at `fp` and writes `data` to it
+    ///
+    /// The file is deleted again if writing it fails
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if [`Format::serialize`] returns an error or writing the file fails
+    fn from_new_file(
+        fp: &Path,
+        file: File,
+        data: T,
+        pretty: bool,
+        format: Fmt,
+    ) -> Result<Self, FileSyncError> {
+        let mut file_sync = Self::from_parts(fp, file, data, FileStamp::default(), pretty, format);
+        if let Err(e) = file_sync.sync() {
+            // Otherwise the empty file would be left behind, and every later attempt to create it would fail
+            let _ = std::fs::remove_file(fp);
+            return Err(e);
+        }
+        Ok(file_sync)
+    }
+
+    /// Creates a `FileSync` type for `file` at `fp` holding `data`, with every setting at its default
+    fn from_parts(
+        fp: &Path,
+        file: File,
+        data: T,
+        stamp: FileStamp,
+        pretty: bool,
+        format: Fmt,
+    ) -> Self {
+        Self {
+            data,
+            file,
+            path: fp.to_path_buf(),
+            format,
+            lock: None,
+            stamp,
+            pretty,
+            atomic: true,
+            on_external_change: ExternalChangePolicy::default(),
+            write_policy: WritePolicy::default(),
+            backups: BackupPolicy::default(),
+            history: History::default(),
+            journal: None,
+            merge_base: None,
+            merge_resolver: None,
+            dirty: false,
+            pending: 0,
+            last_write: None,
+            poisoned: false,
+        }
+    }
+
+    /// Creates a new `FileSync` type loading and syncing data from an already existing file stored as `format`
+    ///
+    /// `pretty` is passed to [`Format::serialize`]
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the creating the [`File`] returns an error
+    ///
+    /// Will return an error if [`Format::deserialize`] returns an error
+    ///
+    /// Will return an error if the data was migrated by a [`Versioned`] format and writing it back fails
+    pub fn load_with_format(fp: &Path, pretty: bool, format: Fmt) -> Result<Self, FileSyncError> {
+        let contents = Self::read_file(fp, &format)?;
+        Self::from_contents(fp, contents, pretty, format)
+    }
+
+    /// Creates a `FileSync` type from the contents of the file at `fp` read by [`FileSync::read_file`], writing them back if they were migrated
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the data was migrated by a [`Versioned`] format and writing it back fails
+    fn from_contents(
+        fp: &Path,
+        (file, data, stamp): (File, T, FileStamp),
+        pretty: bool,
+        format: Fmt,
+    ) -> Result<Self, FileSyncError> {
+        let migration = format.take_migration();
+        let mut file_sync = Self::from_parts(fp, file, data, stamp, pretty, format);
+        if let Some(migration) = migration {
+            file_sync.write_back_migration(migration)?;
+        }
+        Ok(file_sync)
+    }
+
+    /// Writes data that was migrated on load back to the file, after backing up the old file, depending on `migration`
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if copying the file or writing it fails
+    fn write_back_migration(&mut self, migration: Migration) -> Result<(), FileSyncError> {
+        if !migration.write_back {
+            return Ok(());
+        }
+        if migration.backup {
+            let mut name = self.path.file_name().unwrap_or_default().to_os_string();
+            name.push(format!(".v{}.bak", migration.from_version));
+            let backup = self.path.with_file_name(name);
+            std::fs::copy(&self.path, &backup).context(Operation::Write, &backup)?;
+        }
+        self.sync()
+    }
+
+    /// Creates a new `FileSync` type loading and syncing data from an already existing file stored as `format`, or creating a new one if the file doesn't exist
+    ///
+    /// `pretty` is passed to [`Format::serialize`]
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the creating the [`File`] returns an error
+    ///
+    /// Will return an error if [`Format::serialize`] returns an error
+    ///
+    /// Will return an error if [`Format::deserialize`] returns an error
+    pub fn load_or_new_with_format(
+        fp: &Path,
+        data: T,
+        pretty: bool,
+        format: Fmt,
+    ) -> Result<Self, FileSyncError> {
+        Self::load_or_else_with_format(fp, pretty, format, || data)
+    }
+
+    /// [`FileSync::load_or_new_with_format`], but the data for a new file is returned by `default`, which is only called if the file doesn't exist
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if [`FileSync::load_or_new_with_format`] would
+    pub fn load_or_else_with_format<F>(
+        fp: &Path,
+        pretty: bool,
+        format: Fmt,
+        default: F,
+    ) -> Result<Self, FileSyncError>
+    where
+        F: FnOnce() -> T,
+    {
+        match Self::read_or_create(fp, &format, None)? {
+            Opened::Existing(contents) => Self::from_contents(fp, contents, pretty, format),
+            Opened::Created(file) => Self::from_new_file(fp, file, default(), pretty, format),
+        }
+    }
+
+    /// Reads the file at `fp`, or creates it if it doesn't exist
+    ///
+    /// If another process creates the file first, it is read instead. Until that process has written the file it is empty, so an empty file is read again for up to [`CREATE_WAIT`] before giving up
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if reading or creating the file fails, or [`Format::deserialize`] returns an error
+    pub(crate) fn read_or_create(
+        fp: &Path,
+        format: &Fmt,
+        permissions: Option<std::fs::Permissions>,
+    ) -> Result<Opened<T>, FileSyncError> {
+        let mut empty_since: Option<Instant> = None;
+        loop {
+            match Self::read_file(fp, format) {
+                Ok(contents) => return Ok(Opened::Existing(contents)),
+                Err(FileSyncError::IoError { source, .. })
+                    if source.kind() == std::io::ErrorKind::NotFound =>
+                {
+                    match Self::create_file(fp, permissions.clone()) {
+                        Ok(file) => return Ok(Opened::Created(file)),
+                        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+                        Err(e) => return Err(FileSyncError::io(Operation::Create, fp, e)),
+                    }
+                }
+                Err(e) => {
+                    let empty = std::fs::metadata(fp).is_ok_and(|metadata| metadata.len() == 0);
+                    match empty_since.get_or_insert_with(Instant::now) {
+                        since if empty && since.elapsed() < CREATE_WAIT => {
+                            std::thread::sleep(lock::POLL_INTERVAL);
+                        }
+                        _ => return Err(e),
+                    }
+                }
+            }
         }
     }
 
@@ -119,26 +759,25 @@ where
     /// # Errors
     ///
     /// If the function somehow fails to clear the file or seek to the beginning of file, it will return an error
-    fn clear_file(&mut self) -> Result<(), ClearFileError> {
+    fn clear_file(file: &mut File) -> std::io::Result<()> {
         use std::io::{Seek, SeekFrom};
-        self.file.set_len(0).map_err(ClearFileError::SetLenError)?;
-        self.file
-            .seek(SeekFrom::Start(0))
-            .map_err(ClearFileError::SeekError)?;
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
         Ok(())
     }
 
     /// Sets the value of the stored data
     ///
+    /// The file is written depending on [`FileSync::write_policy`]. If the write fails the stored data is restored to its previous value
+    ///
     /// # Errors
     ///
-    /// Returns an error if [`serde_json::to_writer`]/[`serde_json::to_writer_pretty`] returns an error
-    /// Returns an error if it fails to clear the file
+    /// Returns an error if [`Format::serialize`] returns an error
+    /// Returns an error if it fails to write the file
+    /// Returns an error if the file was changed by something else, depending on [`FileSync::on_external_change`]
+    /// Returns [`FileSyncError::RollbackFailed`] if the write failed and the previous state couldn't be restored
     pub fn set(&mut self, data: T) -> Result<(), FileSyncError> {
-        self.clear_file()?;
-        Self::write(&self.file, &self.data, self.pretty)?;
-        self.data = data;
-        Ok(())
+        self.modify(move |stored| *stored = data)
     }
 
     /// Returns an immutable reference to the stored data
@@ -146,38 +785,571 @@ where
         &self.data
     }
 
+    /// Turns on advisory locking against other processes as configured by `locking`, or turns it off if `locking` is `None`
+    ///
+    /// With [`LockMode::Exclusive`] the lock is taken right away, and the stored data is read from the file again if another process changed it before the lock was taken
+    ///
+    /// See the [`lock`] module for how locks are taken
+    ///
+    /// # Errors
+    ///
+    /// Will return [`FileSyncError::Locked`] if the lock is held by another process and `locking.wait` gives up
+    ///
+    /// Will return [`FileSyncError::ModifiedExternally`] without taking the lock if the file was changed by something else and there are changes that haven't been written yet, so they aren't lost
+    ///
+    /// Will return an error if opening the lock file or reading the file fails
+    ///
+    /// Will return an error if [`Format::deserialize`] returns an error
+    pub fn set_locking(&mut self, locking: Option<Locking>) -> Result<(), FileSyncError> {
+        // Drop the old lock first so switching modes doesn't block on our own lock
+        self.lock = None;
+        if let Some(locking) = locking {
+            let lock = FileLock::open(&self.path, locking)?;
+            if locking.mode == LockMode::Exclusive && self.check_stale()? {
+                if self.dirty {
+                    return Err(FileSyncError::ModifiedExternally {
+                        fp: self.path.clone(),
+                    });
+                }
+                self.read_from_disk()?;
+            }
+            self.lock = Some(Arc::new(lock));
+        }
+        Ok(())
+    }
+
     /// Modifies data and syncs the modified data to the file given a `Fn(&mut T)`
     ///
+    /// The file is written depending on [`FileSync::write_policy`]. If the write fails the stored data is restored to its value from before `f` was called
+    ///
     /// # Errors
     ///
-    /// Returns an error if [`serde_json::to_writer`]/[`serde_json::to_writer_pretty`] returns an error
-    /// Returns an error if it fails to clear the file
+    /// Returns an error if [`Format::serialize`] returns an error
+    /// Returns an error if it fails to write the file
+    /// Returns an error if the file was changed by something else, depending on [`FileSync::on_external_change`]
+    /// Returns [`FileSyncError::RollbackFailed`] if the write failed and the previous state couldn't be restored
     pub fn modify<F>(&mut self, f: F) -> Result<(), FileSyncError>
     where
         F: FnOnce(&mut T),
     {
-        (f)(&mut self.data);
-        self.clear_file()?;
-        Self::write(&self.file, &self.data, self.pretty)?;
+        let mut guard = self.borrow_mut()?;
+        (f)(&mut guard);
+        guard.commit()
+    }
+
+    /// Returns a guard that gives mutable access to the stored data and writes it to the file when it is committed or dropped. See [`FileSyncGuard`]
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if [`Format::serialize`] returns an error while taking a snapshot to roll back to
+    /// Returns an error if the file was changed by something else, depending on [`FileSync::on_external_change`]
+    pub fn borrow_mut(&mut self) -> Result<FileSyncGuard<'_, T, Fmt>, FileSyncError> {
+        FileSyncGuard::new(self, false, true)
+    }
+
+    /// Modifies data given a closure that can fail, and syncs the modified data to the file if it succeeds, returning the value it returned
+    ///
+    /// If `f` returns an [`Err`] nothing is written and the stored data is restored to its value from before `f` was called.
+    /// The error is returned as [`FileSyncError::ClosureError`], and can be recovered with [`Box::downcast`]
+    ///
+    /// The file is written depending on [`FileSync::write_policy`]. If the write fails the stored data is restored as well
+    ///
+    /// # Errors
+    ///
+    /// Returns [`FileSyncError::ClosureError`] if `f` returns an error
+    /// Returns an error if [`Format::serialize`] returns an error
+    /// Returns an error if it fails to write the file
+    /// Returns an error if the file was changed by something else, depending on [`FileSync::on_external_change`]
+    /// Returns [`FileSyncError::RollbackFailed`] if the write failed or `f` returned an error, and the previous state couldn't be restored
+    pub fn try_modify<F, R, E>(&mut self, f: F) -> Result<R, FileSyncError>
+    where
+        F: FnOnce(&mut T) -> Result<R, E>,
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        let mut guard = FileSyncGuard::new(self, true, true)?;
+        match (f)(&mut guard) {
+            Ok(value) => {
+                guard.commit()?;
+                Ok(value)
+            }
+            Err(e) => Err(guard.discard(FileSyncError::ClosureError(Box::new(e)))),
+        }
+    }
+
+    /// Writes changes that haven't been written to the file yet because of [`FileSync::write_policy`]. Does nothing if there are none
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if [`Format::serialize`] returns an error
+    /// Returns an error if it fails to write the file
+    /// Returns an error if the file was changed by something else, depending on [`FileSync::on_external_change`]
+    pub fn flush(&mut self) -> Result<(), FileSyncError> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let _guard = self.write_lock()?;
+        self.handle_external_change()?;
+        self.sync()
+    }
+
+    /// Returns `true` if the stored data has changes that haven't been written to the file yet
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Returns `true` if a change that wasn't going to be written right away was cut short by a panic
+    ///
+    /// Only changes that are written right away are snapshotted, so such a change can't be undone and the stored data might be half changed.
+    /// Until [`FileSync::reload`] or [`FileSync::clear_poison`] is called, writing returns [`FileSyncError::Poisoned`] and nothing is written on drop
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned
+    }
+
+    /// Allows the stored data to be written again after [`FileSync::is_poisoned`], keeping whatever it holds now
+    pub fn clear_poison(&mut self) {
+        self.poisoned = false;
+    }
+
+    /// Returns `true` if the next change should be written to the file according to [`FileSync::write_policy`]
+    fn should_write(&self) -> bool {
+        match self.write_policy {
+            WritePolicy::Immediate => true,
+            WritePolicy::Throttle(interval) => self
+                .last_write
+                .is_none_or(|last_write| last_write.elapsed() >= interval),
+            WritePolicy::EveryN(n) => self.pending.saturating_add(1) >= n.get(),
+            WritePolicy::Manual => false,
+        }
+    }
+
+    /// Replaces the stored data with the current contents of the file, discarding any differences including changes that haven't been written yet
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if reading the file fails
+    ///
+    /// Will return an error if [`Format::deserialize`] returns an error, in which case the stored data is left untouched
+    pub fn reload(&mut self) -> Result<(), FileSyncError> {
+        let _guard = self.read_lock()?;
+        self.read_from_disk()
+    }
+
+    /// Returns `true` if the file was changed by something else since it was last read or written by this `FileSync`, including if it was deleted
+    ///
+    /// The size and modification time of the file are checked first. If either differs, the contents are hashed and compared, so a file that was only touched isn't stale
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if reading the metadata or contents of the file fails
+    pub fn is_stale(&self) -> Result<bool, FileSyncError> {
+        let _guard = self.read_lock()?;
+        self.check_stale()
+    }
+
+    /// [`FileSync::is_stale`] without taking a lock
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if reading the metadata or contents of the file fails
+    fn check_stale(&self) -> Result<bool, FileSyncError> {
+        let metadata = match std::fs::metadata(&self.path) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(true),
+            Err(e) => return Err(FileSyncError::io(Operation::Load, &self.path, e)),
+        };
+        if metadata.len() != self.stamp.len {
+            return Ok(true);
+        }
+        if metadata.modified().ok() == self.stamp.modified {
+            return Ok(false);
+        }
+        let bytes = std::fs::read(&self.path).context(Operation::Load, &self.path)?;
+        Ok(FileStamp::hash(&bytes) != self.stamp.hash)
+    }
+
+    /// Applies [`FileSync::on_external_change`] before a write
+    ///
+    /// # Errors
+    ///
+    /// Will return [`FileSyncError::ModifiedExternally`] if the file is stale and [`ExternalChangePolicy::Error`] is used
+    ///
+    /// Will return an error if checking or reloading the file fails
+    fn handle_external_change(&mut self) -> Result<(), FileSyncError> {
+        match self.on_external_change {
+            ExternalChangePolicy::Overwrite => Ok(()),
+            ExternalChangePolicy::Error => {
+                if self.check_stale()? {
+                    Err(FileSyncError::ModifiedExternally {
+                        fp: self.path.clone(),
+                    })
+                } else {
+                    Ok(())
+                }
+            }
+            ExternalChangePolicy::Reload => {
+                if self.check_stale()? {
+                    if self.dirty {
+                        return Err(FileSyncError::ModifiedExternally {
+                            fp: self.path.clone(),
+                        });
+                    }
+                    self.read_from_disk()?;
+                }
+                Ok(())
+            }
+            ExternalChangePolicy::Merge => {
+                if self.check_stale()? {
+                    self.merge_from_disk()?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// [`FileSync::reload`] without taking a lock
+    ///
+    /// The file is opened again, since an atomic write by another process replaces it
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if reading the file fails
+    ///
+    /// Will return an error if [`Format::deserialize`] returns an error, in which case the stored data is left untouched
+    fn read_from_disk(&mut self) -> Result<(), FileSyncError> {
+        let (file, data, stamp) = Self::read_file(&self.path, &self.format)?;
+        self.file = file;
+        self.data = data;
+        self.stamp = stamp;
+        self.dirty = false;
+        self.pending = 0;
+        self.poisoned = false;
+        self.record_merge_base()?;
+        self.reopen_journal()
+    }
+
+    /// Reloads the stored data if the file was changed by something else, returning the data it replaced
+    ///
+    /// If the new contents can't be deserialized the stored data is kept, but the contents are remembered so the same change isn't reported again
+    ///
+    /// Changes that haven't been written yet are never discarded. With [`ExternalChangePolicy::Merge`] the file is merged into them, otherwise the stored data is kept
+    ///
+    /// # Errors
+    ///
+    /// Will return [`FileSyncError::ModifiedExternally`] if there are changes that haven't been written yet and [`ExternalChangePolicy::Merge`] isn't used
+    ///
+    /// Will return an error if checking or reading the file fails, or merging fails
+    ///
+    /// Will return an error if [`Format::deserialize`] returns an error
+    pub(crate) fn poll_external_change(&mut self) -> Result<Option<T>, FileSyncError> {
+        let _guard = self.read_lock()?;
+        if !self.check_stale()? {
+            return Ok(None);
+        }
+        if self.dirty {
+            if self.on_external_change == ExternalChangePolicy::Merge {
+                return self.merge_from_disk().map(Some);
+            }
+            return Err(FileSyncError::ModifiedExternally {
+                fp: self.path.clone(),
+            });
+        }
+        let (file, bytes, stamp) =
+            Self::read_raw(&self.path).context(Operation::Load, &self.path)?;
+        let data = self
+            .format
+            .deserialize(bytes.as_slice())
+            .map_err(|e| FileSyncError::from_format(e, Operation::Load, &self.path))
+            .and_then(|data| Self::replay_journal(&self.path, data, stamp.hash));
+        match data {
+            Ok(data) => {
+                self.file = file;
+                self.stamp = stamp;
+                self.dirty = false;
+                self.pending = 0;
+                let old = std::mem::replace(&mut self.data, data);
+                self.record_merge_base()?;
+                self.reopen_journal()?;
+                Ok(Some(old))
+            }
+            Err(e) => {
+                self.stamp = stamp;
+                Err(e)
+            }
+        }
+    }
+
+    /// Opens the file at `fp` and deserializes its contents, applying its [`journal`] if it has one
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if opening or reading the file fails
+    ///
+    /// Will return an error if [`Format::deserialize`] returns an error, or the journal can't be applied
+    fn read_file(fp: &Path, format: &Fmt) -> Result<(File, T, FileStamp), FileSyncError> {
+        let (file, bytes, stamp) = Self::read_raw(fp).context(Operation::Load, fp)?;
+        let data = format
+            .deserialize(bytes.as_slice())
+            .map_err(|e| FileSyncError::from_format(e, Operation::Load, fp))?;
+        let data = Self::replay_journal(fp, data, stamp.hash)?;
+        Ok((file, data, stamp))
+    }
+
+    /// Opens the file at `fp` and reads its contents
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if opening or reading the file fails
+    fn read_raw(fp: &Path) -> std::io::Result<(File, Vec<u8>, FileStamp)> {
+        use std::io::Read;
+        let mut file = File::options().read(true).write(true).open(fp)?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        let stamp = FileStamp::new(&file.metadata()?, &bytes);
+        Ok((file, bytes, stamp))
+    }
+
+    /// Takes a shared lock if [`LockMode::SharedExclusive`] is used
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if taking the lock fails
+    fn read_lock(&self) -> Result<Option<lock::LockGuard>, FileSyncError> {
+        self.lock
+            .as_ref()
+            .map(FileLock::read)
+            .transpose()
+            .map(Option::flatten)
+    }
+
+    /// Takes an exclusive lock if [`LockMode::SharedExclusive`] is used
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if taking the lock fails
+    fn write_lock(&self) -> Result<Option<lock::LockGuard>, FileSyncError> {
+        self.lock
+            .as_ref()
+            .map(FileLock::write)
+            .transpose()
+            .map(Option::flatten)
+    }
+
+    /// Restores the stored data from `snapshot` after `error` happened while syncing
+    ///
+    /// Serialization and locking happen before the file is touched, so the file is only rewritten from `snapshot` if `error` came from writing it, and never when journaling.
+    /// An atomic write that failed before the rename leaves the file as it was, so it isn't rewritten either
+    ///
+    /// Returns the error that should be given to the caller
+    fn rollback(&mut self, error: FileSyncError, snapshot: &[u8]) -> FileSyncError {
+        match self.format.deserialize(snapshot) {
+            Ok(data) => self.data = data,
+            Err(e) => {
+                return FileSyncError::RollbackFailed {
+                    error: Box::new(error),
+                    rollback_error: Box::new(FileSyncError::from_format(
+                        e,
+                        Operation::Load,
+                        &self.path,
+                    )),
+                }
+            }
+        }
+        // A failed journaled write leaves everything on disk as it was
+        if self.journal.is_none()
+            && matches!(
+                error,
+                FileSyncError::IoError {
+                    op: Operation::Write | Operation::Clear,
+                    ..
+                }
+            )
+            && !self.file_unchanged()
+        {
+            if let Err(e) = self.write_bytes(snapshot) {
+                return FileSyncError::RollbackFailed {
+                    error: Box::new(error),
+                    rollback_error: Box::new(e),
+                };
+            }
+        }
+        error
+    }
+
+    /// Returns `true` if the contents of the file are still the ones it had when it was last read or written by this `FileSync`
+    fn file_unchanged(&self) -> bool {
+        std::fs::read(&self.path).is_ok_and(|bytes| FileStamp::hash(&bytes) == self.stamp.hash)
+    }
+
+    /// Backs up the file depending on [`FileSync::backups`], then writes the stored data to it, either atomically or in place depending on [`FileSync::atomic`]
+    ///
+    /// When journaling, appends to the journal or compacts instead. See [`FileSync::set_journal`]
+    ///
+    /// # Errors
+    ///
+    /// Returns [`FileSyncError::Poisoned`] if [`FileSync::is_poisoned`]
+    /// Returns an error if [`Format::serialize`] returns an error, in which case the file is left untouched
+    /// Returns an error if backing up, writing, renaming or syncing the file fails
+    fn sync(&mut self) -> Result<(), FileSyncError> {
+        if self.poisoned {
+            return Err(FileSyncError::Poisoned {
+                fp: self.path.clone(),
+            });
+        }
+        if self.journal.is_some() {
+            self.sync_journal()?;
+        } else {
+            let bytes = self.serialize()?;
+            self.back_up()?;
+            self.write_bytes(&bytes)?;
+            // Best effort. A leftover journal no longer matches the file, unless the new contents are identical to the snapshot it was written for
+            let _ = std::fs::remove_file(Self::journal_path(&self.path));
+        }
+        self.dirty = false;
+        self.pending = 0;
+        self.last_write = Some(Instant::now());
+        self.record_merge_base()
+    }
+
+    /// Remembers the stored data as the base for merging if [`ExternalChangePolicy::Merge`] is used, or forgets the base otherwise, since it would go out of date
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the data can't be converted to a [`serde_json::Value`]
+    fn record_merge_base(&mut self) -> Result<(), FileSyncError> {
+        self.merge_base = if self.on_external_change == ExternalChangePolicy::Merge {
+            Some(serde_json::to_value(&self.data).context(Operation::Write, &self.path)?)
+        } else {
+            None
+        };
         Ok(())
     }
 
+    /// Replaces the contents of the file with `bytes`, either atomically or in place depending on [`FileSync::atomic`]
+    ///
     /// # Errors
     ///
-    /// Will return an error if [`serde_json::to_writer`]/[`serde_json::to_writer_pretty`] fails
-    fn write(file: &File, value: &T, pretty: bool) -> Result<(), serde_json::Error> {
-        if pretty {
-            serde_json::to_writer_pretty(file, value)?;
+    /// Returns an error if writing, renaming or syncing the file fails
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), FileSyncError> {
+        if self.atomic {
+            self.file =
+                Self::write_atomic(&self.path, bytes).context(Operation::Write, &self.path)?;
         } else {
-            serde_json::to_writer(file, value)?;
+            Self::clear_file(&mut self.file).context(Operation::Clear, &self.path)?;
+            self.file
+                .write_all(bytes)
+                .and_then(|()| self.file.flush())
+                .context(Operation::Write, &self.path)?;
         }
+        let metadata = self.file.metadata().context(Operation::Write, &self.path)?;
+        self.stamp = FileStamp::new(&metadata, bytes);
         Ok(())
     }
+
+    /// Writes `bytes` to a temporary file next to `fp`, flushes it to disk, renames it over `fp` and syncs the parent directory
+    ///
+    /// The temporary file gets the permissions of `fp` if it exists, so they survive the rename
+    ///
+    /// Returns a handle to the newly renamed file. The temporary file is removed if anything fails before the rename
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if creating, writing, syncing or renaming the temporary file fails
+    fn write_atomic(fp: &Path, bytes: &[u8]) -> std::io::Result<File> {
+        let (mut tmp, tmp_path) = Self::create_tmp(fp)?;
+        let result = (|| {
+            if let Ok(metadata) = std::fs::metadata(fp) {
+                tmp.set_permissions(metadata.permissions())?;
+            }
+            tmp.write_all(bytes)?;
+            tmp.sync_all()?;
+            std::fs::rename(&tmp_path, fp)
+        })();
+        if let Err(e) = result {
+            // Best effort, the original error is more important than a leftover temporary file
+            let _ = std::fs::remove_file(&tmp_path);
+            return Err(e);
+        }
+        Self::sync_parent_dir(fp)?;
+        File::options().read(true).write(true).open(fp)
+    }
+
+    /// Creates a temporary file for [`FileSync::write_atomic`] next to `fp`, returning it along with its path
+    ///
+    /// Every call gets a new name made of the process id and a counter, so concurrent writes to the same path never share a temporary file.
+    /// It lives in the same directory as `fp` so the rename never crosses a filesystem boundary
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if creating the file fails for any reason other than the name being taken
+    fn create_tmp(fp: &Path) -> std::io::Result<(File, PathBuf)> {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        loop {
+            let mut name = std::ffi::OsString::from(".");
+            name.push(fp.file_name().unwrap_or_default());
+            name.push(format!(
+                ".{}.{}.tmp",
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::Relaxed)
+            ));
+            let tmp_path = fp.with_file_name(name);
+            // A leftover from a crashed process with the same id is skipped instead of reused
+            match File::options().write(true).create_new(true).open(&tmp_path) {
+                Ok(tmp) => return Ok((tmp, tmp_path)),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Flushes the directory entry of `fp` to disk so a completed rename survives a crash
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if opening or syncing the parent directory fails
+    #[cfg(unix)]
+    fn sync_parent_dir(fp: &Path) -> std::io::Result<()> {
+        let parent = match fp.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        File::open(parent)?.sync_all()
+    }
+
+    /// Directories can't be opened as files on this platform, so this is a no-op
+    #[cfg(not(unix))]
+    fn sync_parent_dir(_fp: &Path) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    /// Serializes the stored data into a buffer
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if [`Format::serialize`] fails
+    fn serialize(&self) -> Result<Vec<u8>, FileSyncError> {
+        let mut bytes = Vec::new();
+        self.format
+            .serialize(&mut bytes, &self.data, self.pretty)
+            .map_err(|e| FileSyncError::from_format(e, Operation::Write, &self.path))?;
+        Ok(bytes)
+    }
+}
+
+impl<T, Fmt> Drop for FileSync<T, Fmt>
+where
+    T: Serialize + DeserializeOwned,
+    Fmt: Format,
+{
+    fn drop(&mut self) {
+        // There's no way to report an error from here, which is why WritePolicy tells people to flush first
+        let _ = self.flush();
+    }
 }
 
-impl<T> std::ops::Deref for FileSync<T>
+impl<T, Fmt> std::ops::Deref for FileSync<T, Fmt>
 where
     T: Serialize + DeserializeOwned,
+    Fmt: Format,
 {
     type Target = T;
     fn deref(&self) -> &T {
@@ -185,9 +1357,10 @@ where
     }
 }
 
-impl<T> std::convert::AsRef<T> for FileSync<T>
+impl<T, Fmt> std::convert::AsRef<T> for FileSync<T, Fmt>
 where
     T: Serialize + DeserializeOwned,
+    Fmt: Format,
 {
     fn as_ref(&self) -> &T {
         self.get()
@@ -0,0 +1,199 @@
+//! Cross-process advisory locking for a [`FileSync`](crate::FileSync)
+//!
+//! Locks are taken on a sidecar file next to the synced file (`data.json` is locked through `data.json.lock`), because atomic writes replace the synced file and a lock on the replaced file would no longer protect anything.
+//! The sidecar file is never deleted, since deleting a lock file can't be done without racing other processes.
+//!
+//! Locks are advisory. They only keep out other processes that also lock the file, such as other `FileSync`s with locking turned on
+
+use crate::{Context, FileSyncError, Operation};
+use std::fs::{File, TryLockError};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How long [`LockWait::Timeout`] sleeps between attempts to take the lock
+pub(crate) const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Configures advisory locking for a [`FileSync`](crate::FileSync)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Locking {
+    pub mode: LockMode,
+    pub wait: LockWait,
+}
+
+/// When a [`FileSync`](crate::FileSync) holds its lock
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockMode {
+    /// An exclusive lock is taken once and held until the `FileSync` is dropped
+    Exclusive,
+    /// A shared lock is taken around each read of the file and an exclusive lock around each write, so multiple processes can take turns using the same file
+    SharedExclusive,
+}
+
+/// What to do when the lock is held by another process
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LockWait {
+    /// Block until the lock is available
+    #[default]
+    Block,
+    /// Return [`FileSyncError::Locked`] right away
+    Try,
+    /// Keep retrying until the lock is available, returning [`FileSyncError::Locked`] if that takes longer than the given duration
+    Timeout(Duration),
+}
+
+/// The sidecar lock file of a [`FileSync`](crate::FileSync)
+#[derive(Debug)]
+pub(crate) struct FileLock {
+    file: File,
+    path: PathBuf,
+    locking: Locking,
+}
+
+/// Releases a lock taken for a single read or write when dropped
+///
+/// Holds on to the [`FileLock`] itself instead of borrowing it, so the `FileSync` can still be mutated while the lock is held
+pub(crate) struct LockGuard(Arc<FileLock>);
+
+impl Drop for LockGuard {
+    fn drop(&mut self) {
+        // Closing the file releases the lock anyways, so there's nothing better to do with an error here
+        let _ = self.0.file.unlock();
+    }
+}
+
+impl FileLock {
+    /// Opens the sidecar lock file of `fp`, creating it if needed
+    ///
+    /// With [`LockMode::Exclusive`] the lock is taken right away
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the lock file can't be opened
+    ///
+    /// Will return [`FileSyncError::Locked`] if the lock is held by another process and `locking.wait` gives up
+    pub(crate) fn open(fp: &Path, locking: Locking) -> Result<Self, FileSyncError> {
+        let path = Self::lock_path(fp);
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .context(Operation::Lock, &path)?;
+        let lock = Self {
+            file,
+            path,
+            locking,
+        };
+        if locking.mode == LockMode::Exclusive {
+            lock.acquire(true)?;
+        }
+        Ok(lock)
+    }
+
+    /// Takes a shared lock for reading the synced file, if [`LockMode::SharedExclusive`] is used
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if taking the lock fails
+    pub(crate) fn read(self: &Arc<Self>) -> Result<Option<LockGuard>, FileSyncError> {
+        self.guard(false)
+    }
+
+    /// Takes an exclusive lock for writing the synced file, if [`LockMode::SharedExclusive`] is used
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if taking the lock fails
+    pub(crate) fn write(self: &Arc<Self>) -> Result<Option<LockGuard>, FileSyncError> {
+        self.guard(true)
+    }
+
+    /// # Errors
+    ///
+    /// Will return an error if taking the lock fails
+    fn guard(self: &Arc<Self>, exclusive: bool) -> Result<Option<LockGuard>, FileSyncError> {
+        match self.locking.mode {
+            LockMode::Exclusive => Ok(None),
+            LockMode::SharedExclusive => {
+                self.acquire(exclusive)?;
+                Ok(Some(LockGuard(Arc::clone(self))))
+            }
+        }
+    }
+
+    /// Takes the lock, waiting according to [`Locking::wait`]
+    ///
+    /// # Errors
+    ///
+    /// Will return [`FileSyncError::Locked`] if the lock is held by another process and waiting gives up
+    ///
+    /// Will return an error if locking fails for any other reason
+    fn acquire(&self, exclusive: bool) -> Result<(), FileSyncError> {
+        match self.locking.wait {
+            LockWait::Block => if exclusive {
+                self.file.lock()
+            } else {
+                self.file.lock_shared()
+            }
+            .context(Operation::Lock, &self.path),
+            LockWait::Try => {
+                if self
+                    .try_acquire(exclusive)
+                    .context(Operation::Lock, &self.path)?
+                {
+                    Ok(())
+                } else {
+                    Err(self.locked())
+                }
+            }
+            LockWait::Timeout(timeout) => {
+                let start = Instant::now();
+                loop {
+                    if self
+                        .try_acquire(exclusive)
+                        .context(Operation::Lock, &self.path)?
+                    {
+                        return Ok(());
+                    }
+                    if start.elapsed() >= timeout {
+                        return Err(self.locked());
+                    }
+                    std::thread::sleep(POLL_INTERVAL);
+                }
+            }
+        }
+    }
+
+    /// Returns `Ok(false)` if the lock is held by another process
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if locking fails for any other reason
+    fn try_acquire(&self, exclusive: bool) -> std::io::Result<bool> {
+        let result = if exclusive {
+            self.file.try_lock()
+        } else {
+            self.file.try_lock_shared()
+        };
+        match result {
+            Ok(()) => Ok(true),
+            Err(TryLockError::WouldBlock) => Ok(false),
+            Err(TryLockError::Error(e)) => Err(e),
+        }
+    }
+
+    fn locked(&self) -> FileSyncError {
+        FileSyncError::Locked {
+            lock_fp: self.path.clone(),
+        }
+    }
+
+    /// Returns the path of the sidecar lock file of `fp`
+    fn lock_path(fp: &Path) -> PathBuf {
+        let mut name = fp.file_name().unwrap_or_default().to_os_string();
+        name.push(".lock");
+        fp.with_file_name(name)
+    }
+}
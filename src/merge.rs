@@ -0,0 +1,309 @@
+//! Three-way merging of external changes, used by [`ExternalChangePolicy::Merge`]
+//!
+//! The base of the merge is the data as it was last read from or written to the file, "ours" is the stored data, and "theirs" is the current contents of the file.
+//! Where only one side changed something, that change is kept. Where both sides changed the same key of an object differently, the keys of those objects are merged one by one.
+//! Anything else changed differently by both sides is a [`Conflict`]
+//!
+//! Merging works on [`serde_json::Value`], so `T` has to be representable as one
+
+use crate::patch::push_token;
+use crate::{Context, ExternalChangePolicy, FileSync, FileSyncError, Format, Operation};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{Map, Value};
+
+type ResolverFn = Box<dyn Fn(&Conflict) -> Option<Value> + Send + Sync>;
+
+/// A place where the stored data and the file were both changed differently since the last read or write
+///
+/// A side is `None` if the value didn't exist there, or was removed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    /// JSON Pointer to the conflicting value
+    pub path: String,
+    pub base: Option<Value>,
+    pub ours: Option<Value>,
+    pub theirs: Option<Value>,
+}
+
+/// Wraps the resolver so [`FileSync`] can still derive [`Debug`]
+pub(crate) struct Resolver(ResolverFn);
+
+impl std::fmt::Debug for Resolver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Resolver").finish_non_exhaustive()
+    }
+}
+
+impl<T, Fmt> FileSync<T, Fmt>
+where
+    T: Serialize + DeserializeOwned,
+    Fmt: Format,
+{
+    /// Sets the function that resolves [`Conflict`]s when merging external changes with [`ExternalChangePolicy::Merge`]
+    ///
+    /// It returns the value to keep at [`Conflict::path`], or `None` to leave it out.
+    /// Without a resolver, conflicts are returned as [`FileSyncError::MergeConflict`]
+    pub fn set_merge_resolver<F>(&mut self, resolver: F)
+    where
+        F: Fn(&Conflict) -> Option<Value> + Send + Sync + 'static,
+    {
+        self.merge_resolver = Some(Resolver(Box::new(resolver)));
+    }
+
+    /// Remembers the stored data as the base for merging, if [`ExternalChangePolicy::Merge`] is used and nothing has been remembered since it was turned on
+    ///
+    /// Called before every change, so the base is there even if the policy was set after the last read or write
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the data can't be converted to a [`serde_json::Value`]
+    pub(crate) fn prepare_merge_base(&mut self) -> Result<(), FileSyncError> {
+        if self.on_external_change == ExternalChangePolicy::Merge
+            && self.merge_base.is_none()
+            && !self.dirty
+        {
+            self.merge_base =
+                Some(serde_json::to_value(&self.data).context(Operation::Write, &self.path)?);
+        }
+        Ok(())
+    }
+
+    /// Merges the current contents of the file into the stored data, returning the data it replaced. See the [module level docs](self)
+    ///
+    /// # Errors
+    ///
+    /// Will return [`FileSyncError::MergeConflict`] if there are conflicts and no resolver was set
+    ///
+    /// Will return [`FileSyncError::ModifiedExternally`] if there are changes that haven't been written yet but nothing to merge them against,
+    /// because [`FileSync::on_external_change`] was set to [`ExternalChangePolicy::Merge`] after they were made
+    ///
+    /// Will return an error if reading the file fails, [`Format::deserialize`] returns an error, or the merged value isn't a valid `T`
+    pub(crate) fn merge_from_disk(&mut self) -> Result<T, FileSyncError> {
+        let (file, bytes, stamp) =
+            Self::read_raw(&self.path).context(Operation::Load, &self.path)?;
+        let mut theirs: Value = self
+            .format
+            .deserialize(bytes.as_slice())
+            .map_err(|e| FileSyncError::from_format(e, Operation::Load, &self.path))?;
+        Self::replay_journal_value(&self.path, &mut theirs, stamp.hash)?;
+        let ours = serde_json::to_value(&self.data).context(Operation::Load, &self.path)?;
+        let base = match &self.merge_base {
+            Some(base) => base,
+            None if !self.dirty => &ours,
+            None => {
+                return Err(FileSyncError::ModifiedExternally {
+                    fp: self.path.clone(),
+                })
+            }
+        };
+        let mut conflicts = Vec::new();
+        let merged = merge(
+            String::new(),
+            Some(base),
+            Some(&ours),
+            Some(&theirs),
+            self.merge_resolver.as_ref(),
+            &mut conflicts,
+        );
+        if !conflicts.is_empty() {
+            return Err(FileSyncError::MergeConflict {
+                fp: self.path.clone(),
+                conflicts,
+            });
+        }
+        let merged = merged.unwrap_or(Value::Null);
+        let changed = merged != theirs;
+        let merged = T::deserialize(merged).context(Operation::Load, &self.path)?;
+        let old = std::mem::replace(&mut self.data, merged);
+        // Whatever was merged in from our side still has to be written
+        self.dirty = self.dirty || changed;
+        self.file = file;
+        self.stamp = stamp;
+        self.merge_base = Some(theirs);
+        self.reopen_journal()?;
+        Ok(old)
+    }
+}
+
+/// Merges `ours` and `theirs` at `path`, returning `None` if the value should be left out
+///
+/// Conflicts are given to `resolver`, or added to `conflicts` if there is none
+fn merge(
+    path: String,
+    base: Option<&Value>,
+    ours: Option<&Value>,
+    theirs: Option<&Value>,
+    resolver: Option<&Resolver>,
+    conflicts: &mut Vec<Conflict>,
+) -> Option<Value> {
+    if ours == theirs || base == theirs {
+        return ours.cloned();
+    }
+    if base == ours {
+        return theirs.cloned();
+    }
+    if let (Some(Value::Object(ours)), Some(Value::Object(theirs))) = (ours, theirs) {
+        let base = base.and_then(Value::as_object);
+        let mut merged = Map::new();
+        let keys = ours
+            .keys()
+            .chain(theirs.keys().filter(|key| !ours.contains_key(*key)));
+        for key in keys {
+            let value = merge(
+                push_token(&path, key),
+                base.and_then(|base| base.get(key)),
+                ours.get(key),
+                theirs.get(key),
+                resolver,
+                conflicts,
+            );
+            if let Some(value) = value {
+                merged.insert(key.clone(), value);
+            }
+        }
+        return Some(Value::Object(merged));
+    }
+    let conflict = Conflict {
+        path,
+        base: base.cloned(),
+        ours: ours.cloned(),
+        theirs: theirs.cloned(),
+    };
+    match resolver {
+        Some(Resolver(resolve)) => resolve(&conflict),
+        None => {
+            conflicts.push(conflict);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Merges `ours` and `theirs` against `base`, returning the merged value and the conflicts
+    fn merge_values(
+        base: Value,
+        ours: Value,
+        theirs: Value,
+        resolver: Option<&Resolver>,
+    ) -> (Option<Value>, Vec<Conflict>) {
+        let mut conflicts = Vec::new();
+        let merged = merge(
+            String::new(),
+            Some(&base),
+            Some(&ours),
+            Some(&theirs),
+            resolver,
+            &mut conflicts,
+        );
+        (merged, conflicts)
+    }
+
+    #[test]
+    fn keeps_changes_from_one_side() {
+        let base = json!({"a": 1, "b": 2});
+
+        let (merged, conflicts) =
+            merge_values(base.clone(), json!({"a": 3, "b": 2}), base.clone(), None);
+        assert_eq!(merged, Some(json!({"a": 3, "b": 2})));
+        assert!(conflicts.is_empty());
+
+        let (merged, conflicts) = merge_values(base.clone(), base, json!({"a": 1, "b": 4}), None);
+        assert_eq!(merged, Some(json!({"a": 1, "b": 4})));
+        assert!(conflicts.is_empty());
+    }
+
+    #[test]
+    fn merges_different_keys() {
+        let (merged, conflicts) = merge_values(
+            json!({"a": 1, "b": 2, "c": 3}),
+            json!({"a": 10, "b": 2, "c": 3, "d": 4}),
+            json!({"a": 1, "b": 20}),
+            None,
+        );
+
+        assert_eq!(merged, Some(json!({"a": 10, "b": 20, "d": 4})));
+        assert!(conflicts.is_empty());
+    }
+
+    #[test]
+    fn merges_nested_objects() {
+        let (merged, conflicts) = merge_values(
+            json!({"outer": {"a": 1, "b": 2}}),
+            json!({"outer": {"a": 10, "b": 2}}),
+            json!({"outer": {"a": 1, "b": 20}}),
+            None,
+        );
+
+        assert_eq!(merged, Some(json!({"outer": {"a": 10, "b": 20}})));
+        assert!(conflicts.is_empty());
+    }
+
+    #[test]
+    fn same_change_on_both_sides_is_not_a_conflict() {
+        let (merged, conflicts) =
+            merge_values(json!({"a": 1}), json!({"a": 2}), json!({"a": 2}), None);
+
+        assert_eq!(merged, Some(json!({"a": 2})));
+        assert!(conflicts.is_empty());
+    }
+
+    #[test]
+    fn different_changes_conflict() {
+        let (_, conflicts) = merge_values(
+            json!({"a": {"b/c": 1}, "d": [1]}),
+            json!({"a": {"b/c": 2}, "d": [1, 2]}),
+            json!({"a": {"b/c": 3}}),
+            None,
+        );
+
+        assert_eq!(
+            conflicts,
+            [
+                Conflict {
+                    path: "/a/b~1c".to_owned(),
+                    base: Some(json!(1)),
+                    ours: Some(json!(2)),
+                    theirs: Some(json!(3)),
+                },
+                Conflict {
+                    path: "/d".to_owned(),
+                    base: Some(json!([1])),
+                    ours: Some(json!([1, 2])),
+                    theirs: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn arrays_are_not_merged() {
+        let (_, conflicts) = merge_values(json!([1, 2]), json!([1, 3]), json!([4, 2]), None);
+
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].path, "");
+    }
+
+    #[test]
+    fn resolver_decides_conflicts() {
+        let resolver = Resolver(Box::new(|conflict: &Conflict| {
+            match conflict.path.as_str() {
+                "/a" => conflict.theirs.clone(),
+                _ => None,
+            }
+        }));
+
+        let (merged, conflicts) = merge_values(
+            json!({"a": 1, "b": 1}),
+            json!({"a": 2, "b": 2}),
+            json!({"a": 3, "b": 3}),
+            Some(&resolver),
+        );
+
+        assert_eq!(merged, Some(json!({"a": 3})));
+        assert!(conflicts.is_empty());
+    }
+}
@@ -0,0 +1,726 @@
+//! [JSON Patch (RFC 6902)](https://www.rfc-editor.org/rfc/rfc6902) and [JSON Merge Patch (RFC 7396)](https://www.rfc-editor.org/rfc/rfc7396) documents, and computing them as the difference between two JSON values
+
+use crate::{Context, FileSync, FileSyncError, Format, Operation};
+use serde::de::DeserializeOwned;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{Map, Value};
+
+/// A single JSON Patch operation. Paths are [JSON Pointers (RFC 6901)](https://www.rfc-editor.org/rfc/rfc6901)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PatchOperation {
+    Add { path: String, value: Value },
+    Remove { path: String },
+    Replace { path: String, value: Value },
+    Move { from: String, path: String },
+    Copy { from: String, path: String },
+    Test { path: String, value: Value },
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum PatchError {
+    #[error("\"{0}\" is not a valid JSON Pointer")]
+    InvalidPointer(String),
+    #[error("Nothing exists at \"{0}\"")]
+    PathNotFound(String),
+    #[error("\"{0}\" is not a valid array index for the array it points into")]
+    InvalidIndex(String),
+    #[error("Test at \"{0}\" failed")]
+    TestFailed(String),
+    #[error("Can't move \"{from}\" into its own child \"{path}\"")]
+    MoveIntoChild { from: String, path: String },
+    #[error("Invalid patch operation: {0}")]
+    InvalidOperation(String),
+}
+
+impl PatchOperation {
+    /// Converts the operation into its JSON form, like `{"op": "add", "path": "/a", "value": 1}`
+    pub fn to_value(&self) -> Value {
+        let mut map = Map::new();
+        let (op, path) = match self {
+            PatchOperation::Add { path, value } => {
+                map.insert("value".to_owned(), value.clone());
+                ("add", path)
+            }
+            PatchOperation::Remove { path } => ("remove", path),
+            PatchOperation::Replace { path, value } => {
+                map.insert("value".to_owned(), value.clone());
+                ("replace", path)
+            }
+            PatchOperation::Move { from, path } => {
+                map.insert("from".to_owned(), Value::String(from.clone()));
+                ("move", path)
+            }
+            PatchOperation::Copy { from, path } => {
+                map.insert("from".to_owned(), Value::String(from.clone()));
+                ("copy", path)
+            }
+            PatchOperation::Test { path, value } => {
+                map.insert("value".to_owned(), value.clone());
+                ("test", path)
+            }
+        };
+        map.insert("op".to_owned(), Value::String(op.to_owned()));
+        map.insert("path".to_owned(), Value::String(path.clone()));
+        Value::Object(map)
+    }
+
+    /// Parses an operation from its JSON form
+    ///
+    /// # Errors
+    ///
+    /// Will return [`PatchError::InvalidOperation`] if `value` isn't a valid operation
+    pub fn from_value(value: &Value) -> Result<Self, PatchError> {
+        let invalid = || PatchError::InvalidOperation(value.to_string());
+        let map = value.as_object().ok_or_else(invalid)?;
+        let string = |key: &str| {
+            map.get(key)
+                .and_then(Value::as_str)
+                .map(str::to_owned)
+                .ok_or_else(invalid)
+        };
+        let value = |key: &str| map.get(key).cloned().ok_or_else(invalid);
+        let path = string("path")?;
+        match map.get("op").and_then(Value::as_str) {
+            Some("add") => Ok(PatchOperation::Add {
+                path,
+                value: value("value")?,
+            }),
+            Some("remove") => Ok(PatchOperation::Remove { path }),
+            Some("replace") => Ok(PatchOperation::Replace {
+                path,
+                value: value("value")?,
+            }),
+            Some("move") => Ok(PatchOperation::Move {
+                from: string("from")?,
+                path,
+            }),
+            Some("copy") => Ok(PatchOperation::Copy {
+                from: string("from")?,
+                path,
+            }),
+            Some("test") => Ok(PatchOperation::Test {
+                path,
+                value: value("value")?,
+            }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl Serialize for PatchOperation {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.to_value().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for PatchOperation {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+        PatchOperation::from_value(&value).map_err(de::Error::custom)
+    }
+}
+
+/// Returns the operations that turn `old` into `new`
+///
+/// Objects are compared key by key and arrays index by index, so the result only touches what changed, using `add`, `remove` and `replace`
+pub fn diff(old: &Value, new: &Value) -> Vec<PatchOperation> {
+    let mut ops = Vec::new();
+    diff_into(&mut ops, String::new(), old, new);
+    ops
+}
+
+fn diff_into(ops: &mut Vec<PatchOperation>, path: String, old: &Value, new: &Value) {
+    match (old, new) {
+        (Value::Object(old), Value::Object(new)) => {
+            for (key, old_value) in old {
+                let child = push_token(&path, key);
+                match new.get(key) {
+                    Some(new_value) => diff_into(ops, child, old_value, new_value),
+                    None => ops.push(PatchOperation::Remove { path: child }),
+                }
+            }
+            for (key, new_value) in new {
+                if !old.contains_key(key) {
+                    ops.push(PatchOperation::Add {
+                        path: push_token(&path, key),
+                        value: new_value.clone(),
+                    });
+                }
+            }
+        }
+        (Value::Array(old), Value::Array(new)) => {
+            for (index, (old_value, new_value)) in old.iter().zip(new).enumerate() {
+                diff_into(
+                    ops,
+                    push_token(&path, &index.to_string()),
+                    old_value,
+                    new_value,
+                );
+            }
+            // Remove from the end so earlier indexes stay valid
+            for index in (new.len()..old.len()).rev() {
+                ops.push(PatchOperation::Remove {
+                    path: push_token(&path, &index.to_string()),
+                });
+            }
+            for new_value in new.iter().skip(old.len()) {
+                ops.push(PatchOperation::Add {
+                    path: push_token(&path, "-"),
+                    value: new_value.clone(),
+                });
+            }
+        }
+        (old, new) if old == new => {}
+        (_, new) => ops.push(PatchOperation::Replace {
+            path,
+            value: new.clone(),
+        }),
+    }
+}
+
+/// Applies `ops` to `doc` in order
+///
+/// Either every operation is applied or, if any of them fails, `doc` is left untouched
+///
+/// # Errors
+///
+/// Will return an error if any operation fails
+pub fn apply(doc: &mut Value, ops: &[PatchOperation]) -> Result<(), PatchError> {
+    let mut patched = doc.clone();
+    apply_in_place(&mut patched, ops)?;
+    *doc = patched;
+    Ok(())
+}
+
+/// Applies `ops` to `doc` in order, leaving it partially patched if an operation fails
+///
+/// # Errors
+///
+/// Will return an error if any operation fails
+pub(crate) fn apply_in_place(doc: &mut Value, ops: &[PatchOperation]) -> Result<(), PatchError> {
+    for op in ops {
+        match op {
+            PatchOperation::Add { path, value } => add(doc, path, value.clone())?,
+            PatchOperation::Remove { path } => {
+                remove(doc, path)?;
+            }
+            PatchOperation::Replace { path, value } => {
+                *pointer_mut(doc, path)? = value.clone();
+            }
+            PatchOperation::Move { from, path } => {
+                if from != path {
+                    if path.starts_with(from.as_str())
+                        && path.as_bytes().get(from.len()) == Some(&b'/')
+                    {
+                        return Err(PatchError::MoveIntoChild {
+                            from: from.clone(),
+                            path: path.clone(),
+                        });
+                    }
+                    let value = remove(doc, from)?;
+                    add(doc, path, value)?;
+                }
+            }
+            PatchOperation::Copy { from, path } => {
+                let value = pointer(doc, from)?.clone();
+                add(doc, path, value)?;
+            }
+            PatchOperation::Test { path, value } => {
+                if pointer(doc, path)? != value {
+                    return Err(PatchError::TestFailed(path.clone()));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Applies the merge patch `patch` to `doc`. Objects in `patch` are merged into `doc` key by key, `null` removes a key, and anything else replaces what was there
+pub fn merge(doc: &mut Value, patch: &Value) {
+    let Value::Object(patch) = patch else {
+        *doc = patch.clone();
+        return;
+    };
+    if !doc.is_object() {
+        *doc = Value::Object(Map::new());
+    }
+    if let Value::Object(doc) = doc {
+        for (key, value) in patch {
+            if value.is_null() {
+                doc.remove(key);
+            } else {
+                merge(doc.entry(key.as_str()).or_insert(Value::Null), value);
+            }
+        }
+    }
+}
+
+/// The JSON Pointers at which two JSON values differ, returned by [`FileSync::diff_with_disk`]
+///
+/// Objects are compared key by key and arrays index by index. Everything else, including a value whose type changed, is compared as a whole
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct JsonDiff {
+    /// Pointers that only exist in the new value
+    pub added: Vec<String>,
+    /// Pointers that only exist in the old value
+    pub removed: Vec<String>,
+    /// Pointers that exist in both values with different contents
+    pub changed: Vec<String>,
+}
+
+impl JsonDiff {
+    /// Compares `old` to `new`
+    pub fn new(old: &Value, new: &Value) -> Self {
+        let mut diff = Self::default();
+        diff.compare(String::new(), old, new);
+        diff
+    }
+
+    /// Returns `true` if the values were equal
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    fn compare(&mut self, path: String, old: &Value, new: &Value) {
+        match (old, new) {
+            (Value::Object(old), Value::Object(new)) => {
+                for (key, old_value) in old {
+                    let child = push_token(&path, key);
+                    match new.get(key) {
+                        Some(new_value) => self.compare(child, old_value, new_value),
+                        None => self.removed.push(child),
+                    }
+                }
+                for key in new.keys().filter(|key| !old.contains_key(*key)) {
+                    self.added.push(push_token(&path, key));
+                }
+            }
+            (Value::Array(old), Value::Array(new)) => {
+                for (index, (old_value, new_value)) in old.iter().zip(new).enumerate() {
+                    self.compare(push_token(&path, &index.to_string()), old_value, new_value);
+                }
+                for index in new.len()..old.len() {
+                    self.removed.push(push_token(&path, &index.to_string()));
+                }
+                for index in old.len()..new.len() {
+                    self.added.push(push_token(&path, &index.to_string()));
+                }
+            }
+            (old, new) if old == new => {}
+            _ => self.changed.push(path),
+        }
+    }
+}
+
+impl<T, Fmt> FileSync<T, Fmt>
+where
+    T: Serialize + DeserializeOwned,
+    Fmt: Format,
+{
+    /// Applies the JSON Patch `ops` to the stored data and writes the result through [`FileSync::set`]
+    ///
+    /// The patch is applied to the data as a [`serde_json::Value`], which then has to deserialize back into a `T`. If anything fails the stored data and the file are left as they were
+    ///
+    /// # Errors
+    ///
+    /// Will return [`FileSyncError::PatchError`] if an operation fails
+    ///
+    /// Will return [`FileSyncError::SerdeJsonError`] if the data can't be converted to a [`serde_json::Value`], or the patched value isn't a valid `T`
+    ///
+    /// Will return an error if [`FileSync::set`] returns an error
+    pub fn apply_patch(&mut self, ops: &[PatchOperation]) -> Result<(), FileSyncError> {
+        let mut value = serde_json::to_value(&self.data).context(Operation::Write, &self.path)?;
+        apply_in_place(&mut value, ops)?;
+        self.set(T::deserialize(value).context(Operation::Write, &self.path)?)
+    }
+
+    /// Applies the JSON Merge Patch `patch` to the stored data and writes the result through [`FileSync::set`]
+    ///
+    /// The patch is applied to the data as a [`serde_json::Value`], which then has to deserialize back into a `T`. If anything fails the stored data and the file are left as they were
+    ///
+    /// # Errors
+    ///
+    /// Will return [`FileSyncError::SerdeJsonError`] if the data can't be converted to a [`serde_json::Value`], or the patched value isn't a valid `T`
+    ///
+    /// Will return an error if [`FileSync::set`] returns an error
+    pub fn apply_merge_patch(&mut self, patch: &Value) -> Result<(), FileSyncError> {
+        let mut value = serde_json::to_value(&self.data).context(Operation::Write, &self.path)?;
+        merge(&mut value, patch);
+        self.set(T::deserialize(value).context(Operation::Write, &self.path)?)
+    }
+
+    /// Compares the stored data to the current contents of the file, to see how they drifted apart
+    ///
+    /// The file is read as a [`serde_json::Value`] through the [`Format`], with its [`journal`](crate::journal) applied. The stored data is the old value,
+    /// so [`JsonDiff::added`] lists what only the file has and [`JsonDiff::removed`] what only the stored data has
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if reading the file fails, or [`Format::deserialize`] returns an error
+    ///
+    /// Will return an error if the data can't be converted to a [`serde_json::Value`], or the journal can't be applied
+    pub fn diff_with_disk(&self) -> Result<JsonDiff, FileSyncError> {
+        let _guard = self.read_lock()?;
+        let (_, bytes, stamp) = Self::read_raw(&self.path).context(Operation::Load, &self.path)?;
+        let mut on_disk: Value = self
+            .format
+            .deserialize(bytes.as_slice())
+            .map_err(|e| FileSyncError::from_format(e, Operation::Load, &self.path))?;
+        Self::replay_journal_value(&self.path, &mut on_disk, stamp.hash)?;
+        let stored = serde_json::to_value(&self.data).context(Operation::Load, &self.path)?;
+        Ok(JsonDiff::new(&stored, &on_disk))
+    }
+}
+
+fn add(doc: &mut Value, path: &str, value: Value) -> Result<(), PatchError> {
+    let Some((parent, last)) = split_pointer(path)? else {
+        *doc = value;
+        return Ok(());
+    };
+    match pointer_mut(doc, parent)? {
+        Value::Object(map) => {
+            map.insert(last, value);
+            Ok(())
+        }
+        Value::Array(array) => {
+            let index = if last == "-" {
+                array.len()
+            } else {
+                parse_index(&last, path)?
+            };
+            if index > array.len() {
+                return Err(PatchError::InvalidIndex(path.to_owned()));
+            }
+            array.insert(index, value);
+            Ok(())
+        }
+        _ => Err(PatchError::PathNotFound(path.to_owned())),
+    }
+}
+
+fn remove(doc: &mut Value, path: &str) -> Result<Value, PatchError> {
+    let Some((parent, last)) = split_pointer(path)? else {
+        return Ok(std::mem::take(doc));
+    };
+    match pointer_mut(doc, parent)? {
+        Value::Object(map) => map
+            .remove(&last)
+            .ok_or_else(|| PatchError::PathNotFound(path.to_owned())),
+        Value::Array(array) => {
+            let index = parse_index(&last, path)?;
+            if index >= array.len() {
+                return Err(PatchError::InvalidIndex(path.to_owned()));
+            }
+            Ok(array.remove(index))
+        }
+        _ => Err(PatchError::PathNotFound(path.to_owned())),
+    }
+}
+
+fn pointer<'a>(doc: &'a Value, path: &str) -> Result<&'a Value, PatchError> {
+    check_pointer(path)?;
+    doc.pointer(path)
+        .ok_or_else(|| PatchError::PathNotFound(path.to_owned()))
+}
+
+fn pointer_mut<'a>(doc: &'a mut Value, path: &str) -> Result<&'a mut Value, PatchError> {
+    check_pointer(path)?;
+    doc.pointer_mut(path)
+        .ok_or_else(|| PatchError::PathNotFound(path.to_owned()))
+}
+
+fn check_pointer(path: &str) -> Result<(), PatchError> {
+    if path.is_empty() || path.starts_with('/') {
+        Ok(())
+    } else {
+        Err(PatchError::InvalidPointer(path.to_owned()))
+    }
+}
+
+/// Splits `path` into the pointer to its parent and its unescaped last token, or returns `None` if it points to the whole document
+fn split_pointer(path: &str) -> Result<Option<(&str, String)>, PatchError> {
+    check_pointer(path)?;
+    Ok(path
+        .rfind('/')
+        .map(|slash| {
+            (
+                path.get(..slash).unwrap_or_default(),
+                path.get(slash..).unwrap_or_default(),
+            )
+        })
+        .map(|(parent, last)| (parent, unescape(last.trim_start_matches('/')))))
+}
+
+/// Parses an array index token, which can only be digits and can't have leading zeros
+fn parse_index(token: &str, path: &str) -> Result<usize, PatchError> {
+    let invalid = || PatchError::InvalidIndex(path.to_owned());
+    if token.is_empty()
+        || !token.bytes().all(|b| b.is_ascii_digit())
+        || (token.len() > 1 && token.starts_with('0'))
+    {
+        return Err(invalid());
+    }
+    token.parse().map_err(|_| invalid())
+}
+
+/// Appends `token` to the pointer `path`, escaping it
+pub(crate) fn push_token(path: &str, token: &str) -> String {
+    format!("{path}/{}", token.replace('~', "~0").replace('/', "~1"))
+}
+
+fn unescape(token: &str) -> String {
+    token.replace("~1", "/").replace("~0", "~")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Applies the patch `ops`, given in its JSON form, to `doc`
+    fn apply_json(mut doc: Value, ops: Value) -> Result<Value, PatchError> {
+        let ops: Vec<PatchOperation> = serde_json::from_value(ops).unwrap();
+        apply(&mut doc, &ops)?;
+        Ok(doc)
+    }
+
+    // The examples from RFC 6902 Appendix A
+
+    #[test]
+    fn a1_add_object_member() {
+        let doc = apply_json(
+            json!({"foo": "bar"}),
+            json!([{"op": "add", "path": "/baz", "value": "qux"}]),
+        );
+        assert_eq!(doc.unwrap(), json!({"baz": "qux", "foo": "bar"}));
+    }
+
+    #[test]
+    fn a2_add_array_element() {
+        let doc = apply_json(
+            json!({"foo": ["bar", "baz"]}),
+            json!([{"op": "add", "path": "/foo/1", "value": "qux"}]),
+        );
+        assert_eq!(doc.unwrap(), json!({"foo": ["bar", "qux", "baz"]}));
+    }
+
+    #[test]
+    fn a3_remove_object_member() {
+        let doc = apply_json(
+            json!({"baz": "qux", "foo": "bar"}),
+            json!([{"op": "remove", "path": "/baz"}]),
+        );
+        assert_eq!(doc.unwrap(), json!({"foo": "bar"}));
+    }
+
+    #[test]
+    fn a4_remove_array_element() {
+        let doc = apply_json(
+            json!({"foo": ["bar", "qux", "baz"]}),
+            json!([{"op": "remove", "path": "/foo/1"}]),
+        );
+        assert_eq!(doc.unwrap(), json!({"foo": ["bar", "baz"]}));
+    }
+
+    #[test]
+    fn a5_replace_value() {
+        let doc = apply_json(
+            json!({"baz": "qux", "foo": "bar"}),
+            json!([{"op": "replace", "path": "/baz", "value": "boo"}]),
+        );
+        assert_eq!(doc.unwrap(), json!({"baz": "boo", "foo": "bar"}));
+    }
+
+    #[test]
+    fn a6_move_value() {
+        let doc = apply_json(
+            json!({
+                "foo": {"bar": "baz", "waldo": "fred"},
+                "qux": {"corge": "grault"}
+            }),
+            json!([{"op": "move", "from": "/foo/waldo", "path": "/qux/thud"}]),
+        );
+        assert_eq!(
+            doc.unwrap(),
+            json!({
+                "foo": {"bar": "baz"},
+                "qux": {"corge": "grault", "thud": "fred"}
+            })
+        );
+    }
+
+    #[test]
+    fn a7_move_array_element() {
+        let doc = apply_json(
+            json!({"foo": ["all", "grass", "cows", "eat"]}),
+            json!([{"op": "move", "from": "/foo/1", "path": "/foo/3"}]),
+        );
+        assert_eq!(
+            doc.unwrap(),
+            json!({"foo": ["all", "cows", "eat", "grass"]})
+        );
+    }
+
+    #[test]
+    fn a8_test_success() {
+        let doc = apply_json(
+            json!({"baz": "qux", "foo": ["a", 2, "c"]}),
+            json!([
+                {"op": "test", "path": "/baz", "value": "qux"},
+                {"op": "test", "path": "/foo/1", "value": 2}
+            ]),
+        );
+        assert_eq!(doc.unwrap(), json!({"baz": "qux", "foo": ["a", 2, "c"]}));
+    }
+
+    #[test]
+    fn a9_test_failure() {
+        let result = apply_json(
+            json!({"baz": "qux"}),
+            json!([{"op": "test", "path": "/baz", "value": "bar"}]),
+        );
+        assert!(matches!(result, Err(PatchError::TestFailed(path)) if path == "/baz"));
+    }
+
+    #[test]
+    fn a10_add_nested_member() {
+        let doc = apply_json(
+            json!({"foo": "bar"}),
+            json!([{"op": "add", "path": "/child", "value": {"grandchild": {}}}]),
+        );
+        assert_eq!(
+            doc.unwrap(),
+            json!({"foo": "bar", "child": {"grandchild": {}}})
+        );
+    }
+
+    #[test]
+    fn a11_ignore_unrecognized_elements() {
+        let doc = apply_json(
+            json!({"foo": "bar"}),
+            json!([{"op": "add", "path": "/baz", "value": "qux", "xyz": 123}]),
+        );
+        assert_eq!(doc.unwrap(), json!({"foo": "bar", "baz": "qux"}));
+    }
+
+    #[test]
+    fn a12_add_to_nonexistent_target() {
+        let result = apply_json(
+            json!({"foo": "bar"}),
+            json!([{"op": "add", "path": "/baz/bat", "value": "qux"}]),
+        );
+        assert!(matches!(result, Err(PatchError::PathNotFound(_))));
+    }
+
+    #[test]
+    fn a14_escape_ordering() {
+        let doc = apply_json(
+            json!({"/": 9, "~1": 10}),
+            json!([{"op": "test", "path": "/~01", "value": 10}]),
+        );
+        assert_eq!(doc.unwrap(), json!({"/": 9, "~1": 10}));
+    }
+
+    #[test]
+    fn a15_compare_strings_and_numbers() {
+        let result = apply_json(
+            json!({"/": 9, "~1": 10}),
+            json!([{"op": "test", "path": "/~01", "value": "10"}]),
+        );
+        assert!(matches!(result, Err(PatchError::TestFailed(_))));
+    }
+
+    #[test]
+    fn a16_add_array_value() {
+        let doc = apply_json(
+            json!({"foo": ["bar"]}),
+            json!([{"op": "add", "path": "/foo/-", "value": ["abc", "def"]}]),
+        );
+        assert_eq!(doc.unwrap(), json!({"foo": ["bar", ["abc", "def"]]}));
+    }
+
+    #[test]
+    fn failed_patch_leaves_document_unchanged() {
+        let mut doc = json!({"foo": "bar"});
+        let ops = [
+            PatchOperation::Remove {
+                path: "/foo".to_owned(),
+            },
+            PatchOperation::Remove {
+                path: "/foo".to_owned(),
+            },
+        ];
+
+        assert!(apply(&mut doc, &ops).is_err());
+        assert_eq!(doc, json!({"foo": "bar"}));
+    }
+
+    #[test]
+    fn invalid_array_indices() {
+        for index in ["+0", "+1", "01", "-1", "", "1e0", " 1"] {
+            let path = format!("/foo/{index}");
+            let result = apply_json(
+                json!({"foo": ["bar", "baz"]}),
+                json!([{"op": "add", "path": path, "value": "qux"}]),
+            );
+            assert!(
+                matches!(result, Err(PatchError::InvalidIndex(_))),
+                "{index:?} was accepted"
+            );
+        }
+    }
+
+    #[test]
+    fn diff_round_trips() {
+        let old = json!({"a": [1, 2, 3], "b": {"c": "d"}, "e": null});
+        let new = json!({"a": [1, 4], "b": {"f": "d"}, "g": true});
+
+        let mut doc = old.clone();
+        apply(&mut doc, &diff(&old, &new)).unwrap();
+
+        assert_eq!(doc, new);
+    }
+
+    #[test]
+    fn json_diff_lists_pointers() {
+        let old = json!({"same": 1, "changed": 1, "removed": 1, "nested": {"a": 1}});
+        let new = json!({"same": 1, "changed": 2, "added": 1, "nested": {"a": 2, "b": 1}});
+
+        let diff = JsonDiff::new(&old, &new);
+
+        assert_eq!(diff.added, ["/nested/b", "/added"]);
+        assert_eq!(diff.removed, ["/removed"]);
+        assert_eq!(diff.changed, ["/changed", "/nested/a"]);
+    }
+
+    #[test]
+    fn json_diff_compares_arrays_by_index() {
+        let shorter = JsonDiff::new(&json!([1, 2, 3]), &json!([1, 4]));
+        let longer = JsonDiff::new(&json!([1]), &json!([1, 2, 3]));
+
+        assert_eq!(shorter.changed, ["/1"]);
+        assert_eq!(shorter.removed, ["/2"]);
+        assert!(shorter.added.is_empty());
+        assert_eq!(longer.added, ["/1", "/2"]);
+        assert!(longer.removed.is_empty() && longer.changed.is_empty());
+    }
+
+    #[test]
+    fn json_diff_escapes_keys() {
+        let diff = JsonDiff::new(&json!({"a/b": 1, "c~d": 1}), &json!({"a/b": 2}));
+
+        assert_eq!(diff.changed, ["/a~1b"]);
+        assert_eq!(diff.removed, ["/c~0d"]);
+    }
+
+    #[test]
+    fn json_diff_changed_type_is_one_change() {
+        let diff = JsonDiff::new(&json!({"a": {"b": 1}}), &json!({"a": [1]}));
+        let root = JsonDiff::new(&json!(1), &json!("1"));
+
+        assert_eq!(diff.changed, ["/a"]);
+        assert!(diff.added.is_empty() && diff.removed.is_empty());
+        assert_eq!(root.changed, [""]);
+        assert!(JsonDiff::new(&json!({"a": [1]}), &json!({"a": [1]})).is_empty());
+    }
+}
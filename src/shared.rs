@@ -0,0 +1,279 @@
+//! A [`FileSync`] that can be shared between threads
+
+use crate::guard::PendingWrite;
+use crate::{Context, FileStamp, FileSync, FileSyncError, Format, Json, Operation};
+use serde::{de::DeserializeOwned, Serialize};
+use std::fs::File;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Instant;
+
+/// A cheaply cloneable handle to a [`FileSync`] that can be shared between threads
+///
+/// Any number of threads can read the data at once through [`SharedFileSync::read`].
+/// Writes are serialized, so they reach the file in the same order they were made
+///
+/// [`SharedFileSync::set`] and [`SharedFileSync::modify`] only block readers while the data is changed and serialized. The file is written while readers can still read the new data.
+/// Journaled and in place writes (see [`FileSync::set_journal`] and [`FileSync::atomic`]) and everything done through [`SharedFileSync::write`] still block readers until they are done
+pub struct SharedFileSync<T, Fmt = Json>
+where
+    T: Serialize + DeserializeOwned,
+    Fmt: Format,
+{
+    inner: Arc<Shared<T, Fmt>>,
+}
+
+struct Shared<T, Fmt>
+where
+    T: Serialize + DeserializeOwned,
+    Fmt: Format,
+{
+    file_sync: RwLock<FileSync<T, Fmt>>,
+    /// Held for the whole of every write, including the disk I/O, so writes don't overtake each other
+    writer: Mutex<()>,
+}
+
+/// Read access to the data of a [`SharedFileSync`]. Writers wait until it is dropped
+pub struct SharedReadGuard<'a, T, Fmt = Json>(RwLockReadGuard<'a, FileSync<T, Fmt>>)
+where
+    T: Serialize + DeserializeOwned,
+    Fmt: Format;
+
+/// Write access to the inner [`FileSync`] of a [`SharedFileSync`], returned by [`SharedFileSync::write`]. Readers and other writers wait until it is dropped
+pub struct SharedWriteGuard<'a, T, Fmt = Json>
+where
+    T: Serialize + DeserializeOwned,
+    Fmt: Format,
+{
+    // Declared first so it is released before the writer lock
+    file_sync: RwLockWriteGuard<'a, FileSync<T, Fmt>>,
+    _writer: MutexGuard<'a, ()>,
+}
+
+impl<T, Fmt> FileSync<T, Fmt>
+where
+    T: Serialize + DeserializeOwned,
+    Fmt: Format,
+{
+    /// Turns this `FileSync` into a [`SharedFileSync`]
+    pub fn into_shared(self) -> SharedFileSync<T, Fmt> {
+        SharedFileSync::new(self)
+    }
+
+    /// Backs up the file and writes `pending` to it atomically, without changing anything in `self`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if backing up or writing the file fails
+    fn write_pending(&self, pending: &PendingWrite) -> Result<File, FileSyncError> {
+        self.back_up()?;
+        Self::write_atomic(&self.path, &pending.bytes).context(Operation::Write, &self.path)
+    }
+
+    /// Finishes a write done by [`FileSync::write_pending`], or rolls the stored data back if `written` is an error
+    ///
+    /// # Errors
+    ///
+    /// Returns `written` if it is an error, or [`FileSyncError::RollbackFailed`] if rolling back failed too
+    ///
+    /// Returns an error if reading the metadata of the new file fails
+    fn finish_pending(
+        &mut self,
+        pending: PendingWrite,
+        written: Result<File, FileSyncError>,
+    ) -> Result<(), FileSyncError> {
+        let installed = written.and_then(|file| {
+            let metadata = file.metadata().context(Operation::Write, &self.path)?;
+            self.file = file;
+            self.stamp = FileStamp::new(&metadata, &pending.bytes);
+            Ok(())
+        });
+        if let Err(e) = installed {
+            return Err(self.rollback(e, &pending.snapshot));
+        }
+        // Best effort, the same as in sync
+        let _ = std::fs::remove_file(Self::journal_path(&self.path));
+        self.dirty = false;
+        self.pending = 0;
+        self.last_write = Some(Instant::now());
+        self.record_merge_base()?;
+        if let Some(entry) = pending.history_entry {
+            self.record_history(entry);
+        }
+        Ok(())
+    }
+}
+
+impl<T, Fmt> SharedFileSync<T, Fmt>
+where
+    T: Serialize + DeserializeOwned,
+    Fmt: Format,
+{
+    /// Wraps `file_sync` so it can be shared between threads
+    pub fn new(file_sync: FileSync<T, Fmt>) -> Self {
+        Self {
+            inner: Arc::new(Shared {
+                file_sync: RwLock::new(file_sync),
+                writer: Mutex::new(()),
+            }),
+        }
+    }
+
+    /// Returns read access to the stored data
+    pub fn read(&self) -> SharedReadGuard<'_, T, Fmt> {
+        SharedReadGuard(self.read_inner())
+    }
+
+    /// Returns write access to the inner [`FileSync`], for anything not covered by the other methods
+    pub fn write(&self) -> SharedWriteGuard<'_, T, Fmt> {
+        let writer = self.lock_writer();
+        SharedWriteGuard {
+            file_sync: self.write_inner(),
+            _writer: writer,
+        }
+    }
+
+    /// Sets the value of the stored data. See [`FileSync::set`]
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if [`FileSync::set`] would
+    pub fn set(&self, data: T) -> Result<(), FileSyncError> {
+        self.modify(move |stored| *stored = data)
+    }
+
+    /// Modifies data and syncs the modified data to the file. See [`FileSync::modify`]
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if [`FileSync::modify`] would
+    pub fn modify<F>(&self, f: F) -> Result<(), FileSyncError>
+    where
+        F: FnOnce(&mut T),
+    {
+        let _writer = self.lock_writer();
+        let pending = {
+            let mut file_sync = self.write_inner();
+            let mut guard = file_sync.borrow_mut()?;
+            f(&mut guard);
+            guard.into_pending()?
+        };
+        let Some(pending) = pending else {
+            return Ok(());
+        };
+        // Only other writers could change the FileSync, and they wait for the writer lock, so readers can keep reading meanwhile
+        let written = self.read_inner().write_pending(&pending);
+        self.write_inner().finish_pending(pending, written)
+    }
+
+    /// Replaces the stored data with the current contents of the file. See [`FileSync::reload`]
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if [`FileSync::reload`] returns an error
+    pub fn reload(&self) -> Result<(), FileSyncError> {
+        self.write().reload()
+    }
+
+    /// Returns the inner [`FileSync`] if this is the only handle to it, or `self` otherwise
+    ///
+    /// # Errors
+    ///
+    /// Returns `self` if other clones of this handle still exist
+    pub fn try_into_inner(self) -> Result<FileSync<T, Fmt>, Self> {
+        Arc::try_unwrap(self.inner)
+            .map(|shared| {
+                shared
+                    .file_sync
+                    .into_inner()
+                    .unwrap_or_else(PoisonError::into_inner)
+            })
+            .map_err(|inner| Self { inner })
+    }
+
+    fn read_inner(&self) -> RwLockReadGuard<'_, FileSync<T, Fmt>> {
+        self.inner
+            .file_sync
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write_inner(&self) -> RwLockWriteGuard<'_, FileSync<T, Fmt>> {
+        self.inner
+            .file_sync
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn lock_writer(&self) -> MutexGuard<'_, ()> {
+        self.inner
+            .writer
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<T, Fmt> Clone for SharedFileSync<T, Fmt>
+where
+    T: Serialize + DeserializeOwned,
+    Fmt: Format,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+impl<T, Fmt> From<FileSync<T, Fmt>> for SharedFileSync<T, Fmt>
+where
+    T: Serialize + DeserializeOwned,
+    Fmt: Format,
+{
+    fn from(file_sync: FileSync<T, Fmt>) -> Self {
+        Self::new(file_sync)
+    }
+}
+
+impl<T, Fmt> std::fmt::Debug for SharedFileSync<T, Fmt>
+where
+    T: Serialize + DeserializeOwned + std::fmt::Debug,
+    Fmt: Format + std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SharedFileSync")
+            .field("inner", &self.inner.file_sync)
+            .finish()
+    }
+}
+
+impl<T, Fmt> std::ops::Deref for SharedReadGuard<'_, T, Fmt>
+where
+    T: Serialize + DeserializeOwned,
+    Fmt: Format,
+{
+    type Target = T;
+    fn deref(&self) -> &T {
+        self.0.get()
+    }
+}
+
+impl<T, Fmt> std::ops::Deref for SharedWriteGuard<'_, T, Fmt>
+where
+    T: Serialize + DeserializeOwned,
+    Fmt: Format,
+{
+    type Target = FileSync<T, Fmt>;
+    fn deref(&self) -> &FileSync<T, Fmt> {
+        &self.file_sync
+    }
+}
+
+impl<T, Fmt> std::ops::DerefMut for SharedWriteGuard<'_, T, Fmt>
+where
+    T: Serialize + DeserializeOwned,
+    Fmt: Format,
+{
+    fn deref_mut(&mut self) -> &mut FileSync<T, Fmt> {
+        &mut self.file_sync
+    }
+}
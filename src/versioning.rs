@@ -0,0 +1,264 @@
+//! Versioned files and migrating old versions of them on load
+//!
+//! A [`Versioned`] format stores the data in an envelope along with a version number, `{"file_sync_version": 2, "data": ...}`.
+//! The key of the version is unusual so the envelope isn't mistaken for data, but data that is an object with exactly the keys `file_sync_version` (a number) and `data` can't be stored unversioned.
+//! When a file with an older version is read, the [`Migrations`] registered for each version in between are run one after the other to turn it into the current version.
+//! Files without an envelope, written before versioning was used, are treated as version 0
+//!
+//! Migrations work on [`serde_json::Value`], so the inner format has to be self-describing
+
+use crate::format::{Format, Json};
+use serde::ser::SerializeStruct;
+use serde::{de::DeserializeOwned, Serialize, Serializer};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::sync::{Mutex, PoisonError};
+
+/// The key of the version in an envelope
+const VERSION_KEY: &str = "file_sync_version";
+
+type MigrationFn =
+    Box<dyn Fn(Value) -> Result<Value, Box<dyn std::error::Error + Send + Sync>> + Send + Sync>;
+
+/// The chain of functions that turn older versions of the data into the current one
+pub struct Migrations {
+    current: u32,
+    steps: BTreeMap<u32, MigrationFn>,
+}
+
+impl Migrations {
+    /// Creates an empty chain for data whose current version is `current`
+    pub fn new(current: u32) -> Self {
+        Self {
+            current,
+            steps: BTreeMap::new(),
+        }
+    }
+
+    /// Registers `f` to turn data of version `from` into data of version `from + 1`
+    #[must_use]
+    pub fn add<F>(mut self, from: u32, f: F) -> Self
+    where
+        F: Fn(Value) -> Result<Value, Box<dyn std::error::Error + Send + Sync>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.steps.insert(from, Box::new(f));
+        self
+    }
+
+    /// Returns the current version
+    pub fn current(&self) -> u32 {
+        self.current
+    }
+
+    /// Runs every migration from `version` up to the current version on `value`
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if `version` is newer than the current version, a step is missing, or a step fails
+    fn migrate<E>(&self, mut version: u32, mut value: Value) -> Result<Value, VersionedError<E>>
+    where
+        E: std::error::Error + 'static,
+    {
+        if version > self.current {
+            return Err(VersionedError::UnsupportedVersion {
+                version,
+                current: self.current,
+            });
+        }
+        while version < self.current {
+            let step = self
+                .steps
+                .get(&version)
+                .ok_or(VersionedError::MissingMigration { from: version })?;
+            value = step(value).map_err(|source| VersionedError::MigrationFailed {
+                from: version,
+                source,
+            })?;
+            version = version.saturating_add(1);
+        }
+        Ok(value)
+    }
+}
+
+impl std::fmt::Debug for Migrations {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Migrations")
+            .field("current", &self.current)
+            .field("steps", &self.steps.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+/// A migration done while deserializing, returned by [`Format::take_migration`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Migration {
+    pub from_version: u32,
+    pub to_version: u32,
+    /// If the migrated data should be written back to the file when it is loaded
+    pub write_back: bool,
+    /// If a copy of the file from before the migration should be kept when it is written back
+    pub backup: bool,
+}
+
+/// A [`Format`] that stores the data in an envelope along with its version, and migrates older versions on load. See the [module level docs](self)
+pub struct Versioned<Fmt = Json> {
+    inner: Fmt,
+    migrations: Migrations,
+    /// If a file that was migrated on load is written back right away, instead of on the next write
+    ///
+    /// Defaults to `true`
+    pub write_back: bool,
+    /// If a file is copied to `<file>.v<old version>.bak` before a migrated version is written back over it
+    ///
+    /// Defaults to `true`
+    pub backup: bool,
+    last_migration: Mutex<Option<Migration>>,
+}
+
+impl<Fmt> Versioned<Fmt>
+where
+    Fmt: Format,
+{
+    /// Creates a format that stores envelopes in `inner`, and migrates old versions with `migrations`
+    pub fn new(inner: Fmt, migrations: Migrations) -> Self {
+        Self {
+            inner,
+            migrations,
+            write_back: true,
+            backup: true,
+            last_migration: Mutex::new(None),
+        }
+    }
+
+    /// Splits `value` into its version and data, treating anything that isn't an envelope as version 0
+    fn open_envelope(value: Value) -> (u32, Value) {
+        match value {
+            Value::Object(mut map) if map.len() == 2 && map.contains_key("data") => {
+                match map
+                    .get(VERSION_KEY)
+                    .and_then(Value::as_u64)
+                    .and_then(|version| u32::try_from(version).ok())
+                {
+                    Some(version) => (version, map.remove("data").unwrap_or(Value::Null)),
+                    None => (0, Value::Object(map)),
+                }
+            }
+            value => (0, value),
+        }
+    }
+}
+
+impl<Fmt> std::fmt::Debug for Versioned<Fmt>
+where
+    Fmt: std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Versioned")
+            .field("inner", &self.inner)
+            .field("migrations", &self.migrations)
+            .field("write_back", &self.write_back)
+            .field("backup", &self.backup)
+            .finish_non_exhaustive()
+    }
+}
+
+/// `{"file_sync_version": version, "data": data}`
+struct Envelope<'a, T: ?Sized> {
+    version: u32,
+    data: &'a T,
+}
+
+impl<T> Serialize for Envelope<'_, T>
+where
+    T: Serialize + ?Sized,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Envelope", 2)?;
+        state.serialize_field(VERSION_KEY, &self.version)?;
+        state.serialize_field("data", self.data)?;
+        state.end()
+    }
+}
+
+impl<Fmt> Format for Versioned<Fmt>
+where
+    Fmt: Format,
+{
+    type Error = VersionedError<Fmt::Error>;
+
+    fn serialize<W, T>(&self, writer: W, value: &T, pretty: bool) -> Result<(), Self::Error>
+    where
+        W: Write,
+        T: Serialize + ?Sized,
+    {
+        let envelope = Envelope {
+            version: self.migrations.current,
+            data: value,
+        };
+        self.inner
+            .serialize(writer, &envelope, pretty)
+            .map_err(VersionedError::Format)
+    }
+
+    fn deserialize<R, T>(&self, reader: R) -> Result<T, Self::Error>
+    where
+        R: Read,
+        T: DeserializeOwned,
+    {
+        let value: Value = self
+            .inner
+            .deserialize(reader)
+            .map_err(VersionedError::Format)?;
+        let (version, data) = Self::open_envelope(value);
+        let data = self.migrations.migrate(version, data)?;
+        let data = serde_json::from_value(data).map_err(VersionedError::Data)?;
+        let migration = (version != self.migrations.current).then_some(Migration {
+            from_version: version,
+            to_version: self.migrations.current,
+            write_back: self.write_back,
+            backup: self.backup,
+        });
+        *self
+            .last_migration
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = migration;
+        Ok(data)
+    }
+
+    fn take_migration(&self) -> Option<Migration> {
+        self.last_migration
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+    }
+
+    fn encrypts(&self) -> bool {
+        self.inner.encrypts()
+    }
+}
+
+/// The error type of [`Versioned`]
+#[derive(thiserror::Error, Debug)]
+pub enum VersionedError<E>
+where
+    E: std::error::Error + 'static,
+{
+    #[error("Inner format error")]
+    Format(#[source] E),
+    #[error("Data doesn't match the current version")]
+    Data(#[source] serde_json::Error),
+    #[error("Version {version} is newer than the current version {current}")]
+    UnsupportedVersion { version: u32, current: u32 },
+    #[error("No migration registered from version {from}")]
+    MissingMigration { from: u32 },
+    #[error("Migration from version {from} failed")]
+    MigrationFailed {
+        from: u32,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+}
@@ -0,0 +1,174 @@
+//! Watching the file behind a [`FileSync`] for changes made by something else
+
+use crate::{FileSync, FileSyncError, Format, Json};
+use serde::{de::DeserializeOwned, Serialize};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// Passed to the callback of a [`WatchedFileSync`]
+#[derive(Debug)]
+pub enum WatchEvent<'a, T> {
+    /// The file was changed and the stored data was reloaded from it
+    Changed { old: &'a T, new: &'a T },
+    /// The file was changed but couldn't be reloaded, usually because its new contents don't deserialize. The stored data keeps its last good value
+    ///
+    /// If the stored data has changes that haven't been written yet, they are kept and this is [`FileSyncError::ModifiedExternally`], unless [`ExternalChangePolicy::Merge`](crate::ExternalChangePolicy::Merge) is used.
+    /// It, or a [`FileSyncError::MergeConflict`], is reported once until the stored data is written or reloaded
+    Error(&'a FileSyncError),
+}
+
+/// A [`FileSync`] that reloads its data in a background thread whenever the file is changed by something else
+///
+/// The file is polled with [`FileSync::is_stale`] every `interval`, which works the same on every platform and notices atomic replacements of the file.
+/// Changes made through [`WatchedFileSync::lock`] don't count as external changes, so they don't trigger the callback
+///
+/// Polling has costs that a notification API like inotify wouldn't have, so pick `interval` with them in mind:
+/// - A change is noticed up to `interval` after it was made
+/// - Every poll reads the metadata of the file while the inner [`FileSync`] is locked
+/// - When the size or modification time changed, the whole file is read and hashed while it is locked, so a large file that is written often keeps
+///   [`WatchedFileSync::lock`] waiting for a read of the file on every poll
+///
+/// An inotify based backend is still open
+///
+/// The callback runs on the background thread while the inner [`FileSync`] is locked, so it must not call [`WatchedFileSync::lock`]
+///
+/// The background thread is stopped when the `WatchedFileSync` is dropped
+pub struct WatchedFileSync<T, Fmt = Json>
+where
+    T: Serialize + DeserializeOwned,
+    Fmt: Format,
+{
+    inner: Arc<Mutex<FileSync<T, Fmt>>>,
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl<T, Fmt> FileSync<T, Fmt>
+where
+    T: Serialize + DeserializeOwned + Send + 'static,
+    Fmt: Format + Send + 'static,
+{
+    /// Starts watching the file for changes made by something else. See [`WatchedFileSync`]
+    pub fn watch<F>(self, interval: Duration, callback: F) -> WatchedFileSync<T, Fmt>
+    where
+        F: FnMut(WatchEvent<'_, T>) + Send + 'static,
+    {
+        WatchedFileSync::new(self, interval, callback)
+    }
+}
+
+impl<T, Fmt> WatchedFileSync<T, Fmt>
+where
+    T: Serialize + DeserializeOwned + Send + 'static,
+    Fmt: Format + Send + 'static,
+{
+    /// Starts watching the file of `file_sync` for changes made by something else, checking every `interval` and calling `callback` for each change
+    pub fn new<F>(file_sync: FileSync<T, Fmt>, interval: Duration, mut callback: F) -> Self
+    where
+        F: FnMut(WatchEvent<'_, T>) + Send + 'static,
+    {
+        let inner = Arc::new(Mutex::new(file_sync));
+        let (stop, stop_receiver) = mpsc::channel();
+        let thread = {
+            let inner = Arc::clone(&inner);
+            std::thread::spawn(move || {
+                // The file stays stale until the unwritten changes are dealt with, so the conflict would otherwise be reported on every poll
+                let mut conflict_reported = false;
+                // Both a stop message and a dropped sender mean it's time to stop
+                while let Err(RecvTimeoutError::Timeout) = stop_receiver.recv_timeout(interval) {
+                    let mut file_sync = inner.lock().unwrap_or_else(PoisonError::into_inner);
+                    match file_sync.poll_external_change() {
+                        Ok(None) => conflict_reported = false,
+                        Ok(Some(old)) => {
+                            conflict_reported = false;
+                            callback(WatchEvent::Changed {
+                                old: &old,
+                                new: file_sync.get(),
+                            });
+                        }
+                        Err(e) if conflict_reported && is_conflict(&e) => {}
+                        Err(e) => {
+                            conflict_reported = is_conflict(&e);
+                            callback(WatchEvent::Error(&e));
+                        }
+                    }
+                }
+            })
+        };
+        Self {
+            inner,
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+impl<T, Fmt> WatchedFileSync<T, Fmt>
+where
+    T: Serialize + DeserializeOwned,
+    Fmt: Format,
+{
+    /// Locks the inner [`FileSync`] so it can be read or modified
+    ///
+    /// The background thread waits while the lock is held
+    pub fn lock(&self) -> MutexGuard<'_, FileSync<T, Fmt>> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Stops watching and returns the inner [`FileSync`]
+    ///
+    /// # Panics
+    ///
+    /// Doesn't panic. The background thread is joined before the inner [`FileSync`] is taken, so nothing else can hold on to it
+    #[allow(clippy::unreachable)]
+    pub fn into_inner(mut self) -> FileSync<T, Fmt> {
+        self.stop();
+        let inner = Arc::clone(&self.inner);
+        drop(self);
+        match Arc::try_unwrap(inner) {
+            Ok(mutex) => mutex.into_inner().unwrap_or_else(PoisonError::into_inner),
+            Err(_) => unreachable!("WatchedFileSync background thread outlived stop"),
+        }
+    }
+
+    /// Stops the background thread and waits for it to finish
+    fn stop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            // A panic in the callback already ended the thread, there's nothing left to clean up
+            let _ = thread.join();
+        }
+    }
+}
+
+impl<T, Fmt> Drop for WatchedFileSync<T, Fmt>
+where
+    T: Serialize + DeserializeOwned,
+    Fmt: Format,
+{
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl<T, Fmt> std::fmt::Debug for WatchedFileSync<T, Fmt>
+where
+    T: Serialize + DeserializeOwned + std::fmt::Debug,
+    Fmt: Format + std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WatchedFileSync")
+            .field("inner", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Returns `true` if `e` is about unwritten changes clashing with the file, which lasts until they are written or discarded
+fn is_conflict(e: &FileSyncError) -> bool {
+    matches!(
+        e,
+        FileSyncError::ModifiedExternally { .. } | FileSyncError::MergeConflict { .. }
+    )
+}
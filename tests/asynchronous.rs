@@ -0,0 +1,84 @@
+#![cfg(feature = "tokio")]
+
+mod common;
+
+use common::temp_path;
+use file_sync::{AsyncFileSync, FileSyncError};
+
+#[tokio::test]
+async fn new_set_and_modify_write_file() {
+    let fp = temp_path("async_new_set_and_modify_write_file");
+    let file_sync = AsyncFileSync::new(&fp, vec![1], false).await.unwrap();
+
+    file_sync.set(vec![2]).await.unwrap();
+    file_sync.modify(|v| v.push(3)).await.unwrap();
+
+    assert_eq!(*file_sync.read(), [2, 3]);
+    assert_eq!(std::fs::read_to_string(&fp).unwrap(), "[2,3]");
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[tokio::test]
+async fn load_and_load_or_new() {
+    let fp = temp_path("async_load_and_load_or_new");
+    assert!(matches!(
+        AsyncFileSync::<i32>::load(&fp, false).await,
+        Err(FileSyncError::IoError { .. })
+    ));
+
+    let created = AsyncFileSync::load_or_new(&fp, 1, false).await.unwrap();
+    let loaded = AsyncFileSync::load_or_new(&fp, 2, false).await.unwrap();
+    assert_eq!(*loaded.read(), 1);
+    drop((created, loaded));
+
+    std::fs::write(&fp, "5").unwrap();
+    let file_sync = AsyncFileSync::<i32>::load(&fp, false).await.unwrap();
+    assert_eq!(*file_sync.read(), 5);
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[tokio::test]
+async fn errors_are_the_same_as_blocking() {
+    let fp = temp_path("async_errors_are_the_same_as_blocking");
+    std::fs::write(&fp, "[1,2").unwrap();
+
+    let e = AsyncFileSync::<Vec<i32>>::load(&fp, false)
+        .await
+        .unwrap_err();
+
+    assert!(matches!(e, FileSyncError::SerdeJsonError { fp: path, .. } if path == fp));
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[tokio::test]
+async fn clones_share_data() {
+    let fp = temp_path("async_clones_share_data");
+    let file_sync = AsyncFileSync::new(&fp, 0, false).await.unwrap();
+    let tasks: Vec<_> = (0..4)
+        .map(|_| {
+            let file_sync = file_sync.clone();
+            tokio::spawn(async move { file_sync.modify(|n| *n += 1).await })
+        })
+        .collect();
+
+    for task in tasks {
+        task.await.unwrap().unwrap();
+    }
+
+    assert_eq!(*file_sync.read(), 4);
+    assert_eq!(std::fs::read_to_string(&fp).unwrap(), "4");
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[tokio::test]
+#[should_panic(expected = "in modify")]
+async fn panic_in_modify_is_resumed() {
+    let fp = temp_path("async_panic_in_modify_is_resumed");
+    let file_sync = AsyncFileSync::new(&fp, 0, false).await.unwrap();
+    std::fs::remove_file(&fp).unwrap();
+
+    let _ = file_sync.modify(|_| panic!("in modify")).await;
+}
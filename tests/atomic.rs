@@ -0,0 +1,43 @@
+mod common;
+
+use common::temp_path;
+use file_sync::FileSync;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+#[test]
+fn concurrent_writers_never_expose_partial_files() {
+    let fp = Arc::new(temp_path("concurrent_writers_never_expose_partial_files"));
+    drop(FileSync::new(fp.as_path(), vec![0; 1000], false).unwrap());
+    let done = Arc::new(AtomicBool::new(false));
+
+    let reader = {
+        let fp = Arc::clone(&fp);
+        let done = Arc::clone(&done);
+        thread::spawn(move || {
+            while !done.load(Ordering::Relaxed) {
+                let contents = std::fs::read_to_string(fp.as_path()).unwrap();
+                serde_json::from_str::<Vec<usize>>(&contents).unwrap();
+            }
+        })
+    };
+    let writers: Vec<_> = (0..4)
+        .map(|i| {
+            let fp = Arc::clone(&fp);
+            thread::spawn(move || {
+                let mut file_sync = FileSync::<Vec<usize>>::load(&fp, false).unwrap();
+                for n in 0..100 {
+                    file_sync.set(vec![i * 1000 + n; 1000]).unwrap();
+                }
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.join().unwrap();
+    }
+    done.store(true, Ordering::Relaxed);
+    reader.join().unwrap();
+
+    std::fs::remove_file(fp.as_path()).unwrap();
+}
@@ -0,0 +1,135 @@
+mod common;
+
+use common::temp_path;
+use file_sync::{BackupPolicy, FileSync, FileSyncError};
+use std::path::{Path, PathBuf};
+
+fn rotated_path(fp: &Path, n: usize) -> PathBuf {
+    let mut name = fp.file_name().unwrap().to_os_string();
+    name.push(format!(".{n}"));
+    fp.with_file_name(name)
+}
+
+fn backup_dir(name: &str) -> PathBuf {
+    let dir = temp_path(name).with_extension("backups");
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+fn contents(paths: &[PathBuf]) -> Vec<String> {
+    paths
+        .iter()
+        .map(|path| std::fs::read_to_string(path).unwrap())
+        .collect()
+}
+
+#[test]
+fn rotate_keeps_newest_first() {
+    let fp = temp_path("rotate_keeps_newest_first");
+    let mut file_sync = FileSync::new(&fp, 0, false).unwrap();
+    file_sync.backups = BackupPolicy::Rotate { count: 2 };
+
+    file_sync.set(1).unwrap();
+    assert_eq!(std::fs::read_to_string(rotated_path(&fp, 1)).unwrap(), "0");
+    file_sync.set(2).unwrap();
+    file_sync.set(3).unwrap();
+
+    let backups = file_sync.list_backups().unwrap();
+    assert_eq!(backups, [rotated_path(&fp, 1), rotated_path(&fp, 2)]);
+    assert_eq!(contents(&backups), ["2", "1"]);
+    assert!(!rotated_path(&fp, 3).exists());
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+    for backup in backups {
+        std::fs::remove_file(backup).unwrap();
+    }
+}
+
+#[test]
+fn timestamped_prunes_oldest() {
+    let fp = temp_path("timestamped_prunes_oldest");
+    let dir = backup_dir("timestamped_prunes_oldest");
+    let mut file_sync = FileSync::new(&fp, 0, false).unwrap();
+    file_sync.backups = BackupPolicy::Timestamped {
+        dir: dir.clone(),
+        keep: 2,
+    };
+
+    for n in 1..=4 {
+        file_sync.set(n).unwrap();
+    }
+
+    let backups = file_sync.list_backups().unwrap();
+    assert_eq!(contents(&backups), ["3", "2"]);
+    assert!(backups.iter().all(|backup| backup.parent() == Some(&*dir)));
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn timestamped_keep_zero_keeps_nothing() {
+    let fp = temp_path("timestamped_keep_zero_keeps_nothing");
+    let dir = backup_dir("timestamped_keep_zero_keeps_nothing");
+    let mut file_sync = FileSync::new(&fp, 0, false).unwrap();
+    file_sync.backups = BackupPolicy::Timestamped {
+        dir: dir.clone(),
+        keep: 0,
+    };
+
+    file_sync.set(1).unwrap();
+
+    assert!(file_sync.list_backups().unwrap().is_empty());
+    assert!(!dir.exists());
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[test]
+fn restore_backup_sets_data_and_backs_up_current() {
+    let fp = temp_path("restore_backup_sets_data_and_backs_up_current");
+    let mut file_sync = FileSync::new(&fp, 0, false).unwrap();
+    file_sync.backups = BackupPolicy::Rotate { count: 3 };
+    file_sync.set(1).unwrap();
+    file_sync.set(2).unwrap();
+
+    file_sync.restore_backup(2).unwrap();
+
+    assert_eq!(*file_sync.get(), 0);
+    assert_eq!(std::fs::read_to_string(&fp).unwrap(), "0");
+    // The data from before the restore is the newest backup, so it can be undone
+    assert_eq!(
+        contents(&file_sync.list_backups().unwrap()),
+        ["2", "1", "0"]
+    );
+    file_sync.restore_backup(1).unwrap();
+    assert_eq!(*file_sync.get(), 2);
+    let backups = file_sync.list_backups().unwrap();
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+    for backup in backups {
+        std::fs::remove_file(backup).unwrap();
+    }
+}
+
+#[test]
+fn restore_missing_backup_is_an_error() {
+    let fp = temp_path("restore_missing_backup_is_an_error");
+    let mut file_sync = FileSync::new(&fp, 0, false).unwrap();
+    file_sync.backups = BackupPolicy::Rotate { count: 2 };
+    file_sync.set(1).unwrap();
+
+    assert!(matches!(
+        file_sync.restore_backup(2),
+        Err(FileSyncError::BackupNotFound { n: 2 })
+    ));
+    assert!(matches!(
+        file_sync.restore_backup(0),
+        Err(FileSyncError::BackupNotFound { n: 0 })
+    ));
+    assert_eq!(*file_sync.get(), 1);
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+    std::fs::remove_file(rotated_path(&fp, 1)).unwrap();
+}
@@ -0,0 +1,148 @@
+mod common;
+
+use common::temp_path;
+use file_sync::{
+    FileSync, FileSyncError, LockMode, LockWait, Locking, Migrations, OnInvalidFile, Versioned,
+};
+
+#[test]
+fn replaces_unparseable_file() {
+    let fp = temp_path("replaces_unparseable_file");
+    std::fs::write(&fp, "[1,2").unwrap();
+
+    let file_sync = FileSync::builder(&fp)
+        .on_invalid(OnInvalidFile::Replace)
+        .open_or_create(|| vec![7])
+        .unwrap();
+
+    assert_eq!(*file_sync.get(), vec![7]);
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[test]
+fn keeps_file_of_other_shape() {
+    let fp = temp_path("keeps_file_of_other_shape");
+    std::fs::write(&fp, r#"{"a":1}"#).unwrap();
+
+    let result = FileSync::<Vec<i32>>::builder(&fp)
+        .on_invalid(OnInvalidFile::Replace)
+        .open_or_create(Vec::new);
+
+    assert!(matches!(result, Err(FileSyncError::SerdeJsonError { .. })));
+    assert_eq!(std::fs::read_to_string(&fp).unwrap(), r#"{"a":1}"#);
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[test]
+fn keeps_file_of_newer_version() {
+    let fp = temp_path("keeps_file_of_newer_version");
+    let contents = r#"{"file_sync_version":5,"data":[1,2,3]}"#;
+    std::fs::write(&fp, contents).unwrap();
+
+    let result = FileSync::<Vec<i32>>::builder(&fp)
+        .format(Versioned::new(file_sync::Json, Migrations::new(1)))
+        .on_invalid(OnInvalidFile::BackUpAndReplace)
+        .open_or_create(Vec::new);
+
+    assert!(result.is_err());
+    assert_eq!(std::fs::read_to_string(&fp).unwrap(), contents);
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn permissions_apply_to_new_file() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let fp = temp_path("permissions_apply_to_new_file");
+    let mut file_sync = FileSync::builder(&fp)
+        .permissions(std::fs::Permissions::from_mode(0o600))
+        .create(1)
+        .unwrap();
+    let mode = || std::fs::metadata(&fp).unwrap().permissions().mode() & 0o777;
+    assert_eq!(mode(), 0o600);
+
+    // An atomic write replaces the file, but keeps its permissions
+    file_sync.set(2).unwrap();
+
+    assert_eq!(mode(), 0o600);
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[test]
+fn create_dirs_creates_parents() {
+    let dir = temp_path("create_dirs_creates_parents");
+    let _ = std::fs::remove_dir_all(&dir);
+    let fp = dir.join("a").join("data.json");
+
+    assert!(FileSync::builder(&fp).create(1).is_err());
+    let file_sync = FileSync::builder(&fp).create_dirs(true).create(1).unwrap();
+
+    assert_eq!(std::fs::read_to_string(&fp).unwrap(), "1");
+    drop(file_sync);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn indent_writes_pretty_json_with_indent() {
+    let fp = temp_path("indent_writes_pretty_json_with_indent");
+
+    let mut file_sync = FileSync::builder(&fp).indent("\t").create(vec![1]).unwrap();
+    file_sync.modify(|v| v.push(2)).unwrap();
+
+    assert!(file_sync.pretty);
+    assert_eq!(std::fs::read_to_string(&fp).unwrap(), "[\n\t1,\n\t2\n]");
+    drop(file_sync);
+    let file_sync = FileSync::<Vec<i32>>::builder(&fp)
+        .indent("\t")
+        .open()
+        .unwrap();
+    assert_eq!(*file_sync.get(), [1, 2]);
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn non_atomic_writes_in_place() {
+    use std::os::unix::fs::MetadataExt;
+
+    let fp = temp_path("non_atomic_writes_in_place");
+    let ino = || std::fs::metadata(&fp).unwrap().ino();
+    let mut atomic = FileSync::builder(&fp).create(1).unwrap();
+    let before = ino();
+    atomic.set(2).unwrap();
+    assert_ne!(ino(), before);
+    drop(atomic);
+
+    let mut in_place = FileSync::<i32>::builder(&fp).atomic(false).open().unwrap();
+    let before = ino();
+    in_place.set(3).unwrap();
+
+    assert!(!in_place.atomic);
+    assert_eq!(ino(), before);
+    assert_eq!(std::fs::read_to_string(&fp).unwrap(), "3");
+    drop(in_place);
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[test]
+fn locking_takes_lock() {
+    let fp = temp_path("locking_takes_lock");
+    let exclusive = Locking {
+        mode: LockMode::Exclusive,
+        wait: LockWait::Try,
+    };
+    let holder = FileSync::builder(&fp).locking(exclusive).create(1).unwrap();
+
+    let result = FileSync::<i32>::builder(&fp).locking(exclusive).open();
+
+    assert!(matches!(result, Err(FileSyncError::Locked { .. })));
+    drop(holder);
+    let mut name = fp.file_name().unwrap().to_os_string();
+    name.push(".lock");
+    std::fs::remove_file(&fp).unwrap();
+    std::fs::remove_file(fp.with_file_name(name)).unwrap();
+}
@@ -0,0 +1,131 @@
+mod common;
+
+use common::temp_path;
+use file_sync::checksum::ChecksumError;
+use file_sync::{BackupPolicy, Checksummed, FileSync, FileSyncError, Json};
+use std::path::{Path, PathBuf};
+
+const BACKUPS: BackupPolicy = BackupPolicy::Rotate { count: 3 };
+
+fn with_suffix(fp: &Path, suffix: &str) -> PathBuf {
+    let mut name = fp.file_name().unwrap().to_os_string();
+    name.push(suffix);
+    fp.with_file_name(name)
+}
+
+/// Writes 0, 1 and 2 to the file, leaving 2 in the file and 1 and 0 in its backups
+fn write_with_backups(fp: &Path) {
+    let mut file_sync = FileSync::new_with_format(fp, 0, false, Checksummed::new(Json)).unwrap();
+    file_sync.backups = BACKUPS;
+    file_sync.set(1).unwrap();
+    file_sync.set(2).unwrap();
+}
+
+/// Flips the last byte of the payload, leaving the header as it was
+fn corrupt(fp: &Path) {
+    let mut bytes = std::fs::read(fp).unwrap();
+    *bytes.last_mut().unwrap() ^= 1;
+    std::fs::write(fp, bytes).unwrap();
+}
+
+fn remove(fp: &Path) {
+    for suffix in ["", ".1", ".2", ".3", ".corrupt"] {
+        let _ = std::fs::remove_file(with_suffix(fp, suffix));
+    }
+}
+
+#[test]
+fn restores_newest_intact_backup() {
+    let fp = temp_path("restores_newest_intact_backup");
+    write_with_backups(&fp);
+    corrupt(&fp);
+    corrupt(&with_suffix(&fp, ".1"));
+    let corrupted = std::fs::read(&fp).unwrap();
+
+    let file_sync = FileSync::<i32, _>::load_or_restore_with_format(
+        &fp,
+        false,
+        Checksummed::new(Json),
+        BACKUPS,
+    )
+    .unwrap();
+
+    assert_eq!(*file_sync.get(), 0);
+    assert_eq!(
+        std::fs::read(&fp).unwrap(),
+        std::fs::read(with_suffix(&fp, ".2")).unwrap()
+    );
+    assert_eq!(
+        std::fs::read(with_suffix(&fp, ".corrupt")).unwrap(),
+        corrupted
+    );
+    drop(file_sync);
+    remove(&fp);
+}
+
+#[test]
+fn restores_file_with_malformed_header() {
+    let fp = temp_path("restores_file_with_malformed_header");
+    write_with_backups(&fp);
+    std::fs::write(&fp, "CRC32:not hex\n2").unwrap();
+
+    let file_sync = FileSync::<i32, _>::load_or_restore_with_format(
+        &fp,
+        false,
+        Checksummed::new(Json),
+        BACKUPS,
+    )
+    .unwrap();
+
+    assert_eq!(*file_sync.get(), 1);
+    assert_eq!(
+        std::fs::read_to_string(with_suffix(&fp, ".corrupt")).unwrap(),
+        "CRC32:not hex\n2"
+    );
+    drop(file_sync);
+    remove(&fp);
+}
+
+#[test]
+fn fails_without_intact_backup() {
+    let fp = temp_path("fails_without_intact_backup");
+    write_with_backups(&fp);
+    for suffix in ["", ".1", ".2"] {
+        corrupt(&with_suffix(&fp, suffix));
+    }
+
+    let e = FileSync::<i32, _>::load_or_restore_with_format(
+        &fp,
+        false,
+        Checksummed::new(Json),
+        BACKUPS,
+    )
+    .unwrap_err();
+
+    assert!(matches!(e, FileSyncError::ChecksumMismatch { .. }));
+    assert!(!with_suffix(&fp, ".corrupt").exists());
+    remove(&fp);
+}
+
+#[test]
+fn malformed_header_without_backups_is_an_error() {
+    let fp = temp_path("malformed_header_without_backups_is_an_error");
+    std::fs::write(&fp, "CRC32:\n1").unwrap();
+
+    let e = FileSync::<i32, _>::load_or_restore_with_format(
+        &fp,
+        false,
+        Checksummed::new(Json),
+        BACKUPS,
+    )
+    .unwrap_err();
+
+    match e {
+        FileSyncError::FormatError { source, .. } => assert!(matches!(
+            source.downcast_ref::<ChecksumError>(),
+            Some(ChecksumError::InvalidHeader)
+        )),
+        e => panic!("unexpected error: {e}"),
+    }
+    remove(&fp);
+}
@@ -0,0 +1,8 @@
+use std::path::PathBuf;
+
+/// Returns a path in the temp directory unique to this test process and `name`, deleting anything left there by an earlier run
+pub fn temp_path(name: &str) -> PathBuf {
+    let fp = std::env::temp_dir().join(format!("file_sync_{}_{name}.json", std::process::id()));
+    let _ = std::fs::remove_file(&fp);
+    fp
+}
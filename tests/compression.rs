@@ -0,0 +1,97 @@
+mod common;
+
+use common::temp_path;
+use file_sync::{Codec, Compressed, FileSync, FileSyncError, Json, Operation};
+use std::io::{Error, ErrorKind};
+
+const MAGIC: &[u8] = b"RLE\0";
+
+/// Run-length encoding, stored as pairs of a count and a byte after [`MAGIC`]
+#[derive(Debug)]
+struct Rle;
+
+impl Codec for Rle {
+    fn magic(&self) -> &[u8] {
+        MAGIC
+    }
+
+    fn compress(&self, bytes: &[u8]) -> std::io::Result<Vec<u8>> {
+        let mut out = MAGIC.to_vec();
+        for chunk in bytes.chunk_by(|a, b| a == b) {
+            for run in chunk.chunks(u8::MAX as usize) {
+                out.push(run.len() as u8);
+                out.push(run[0]);
+            }
+        }
+        Ok(out)
+    }
+
+    fn decompress(&self, bytes: &[u8]) -> std::io::Result<Vec<u8>> {
+        let pairs = &bytes[MAGIC.len()..];
+        if !pairs.len().is_multiple_of(2) {
+            return Err(Error::new(ErrorKind::InvalidData, "truncated run"));
+        }
+        Ok(pairs
+            .chunks(2)
+            .flat_map(|pair| std::iter::repeat_n(pair[1], pair[0] as usize))
+            .collect())
+    }
+}
+
+#[test]
+fn round_trips() {
+    let fp = temp_path("compression_round_trips");
+    let data = "a".repeat(1000);
+    let file_sync =
+        FileSync::new_with_format(&fp, data.clone(), false, Compressed::new(Rle, Json)).unwrap();
+    drop(file_sync);
+
+    let contents = std::fs::read(&fp).unwrap();
+    assert!(contents.starts_with(MAGIC));
+    assert!(contents.len() < 1000);
+    let file_sync =
+        FileSync::<String, _>::load_with_format(&fp, false, Compressed::new(Rle, Json)).unwrap();
+    assert_eq!(*file_sync.get(), data);
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[test]
+fn reads_uncompressed_files() {
+    let fp = temp_path("compression_reads_uncompressed_files");
+    std::fs::write(&fp, "[1,2,3]").unwrap();
+
+    let mut file_sync =
+        FileSync::<Vec<i32>, _>::load_with_format(&fp, false, Compressed::new(Rle, Json)).unwrap();
+
+    assert_eq!(*file_sync.get(), [1, 2, 3]);
+    file_sync.modify(|v| v.push(4)).unwrap();
+    assert!(std::fs::read(&fp).unwrap().starts_with(MAGIC));
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[test]
+fn corrupt_stream_is_an_error() {
+    let fp = temp_path("compression_corrupt_stream_is_an_error");
+    let mut contents = Rle.compress(b"[1,2,3]").unwrap();
+    contents.pop();
+    std::fs::write(&fp, contents).unwrap();
+
+    let e = FileSync::<Vec<i32>, _>::load_with_format(&fp, false, Compressed::new(Rle, Json))
+        .unwrap_err();
+
+    match e {
+        FileSyncError::IoError {
+            op,
+            fp: path,
+            source,
+        } => {
+            assert_eq!(op, Operation::Load);
+            assert_eq!(path, fp);
+            assert_eq!(source.kind(), ErrorKind::InvalidData);
+        }
+        e => panic!("unexpected error: {e}"),
+    }
+    std::fs::remove_file(&fp).unwrap();
+}
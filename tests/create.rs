@@ -0,0 +1,102 @@
+mod common;
+
+use common::temp_path;
+use file_sync::{FileSync, FileSyncError};
+use std::collections::HashMap;
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const THREADS: usize = 8;
+
+#[test]
+fn new_fails_if_file_exists() {
+    let fp = temp_path("new_fails_if_file_exists");
+    std::fs::write(&fp, "[1,2,3]").unwrap();
+
+    let result = FileSync::new(&fp, vec![4, 5, 6], false);
+
+    assert!(matches!(
+        result,
+        Err(FileSyncError::FileAlreadyExists { .. })
+    ));
+    assert_eq!(std::fs::read_to_string(&fp).unwrap(), "[1,2,3]");
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[test]
+fn concurrent_new_creates_once() {
+    let fp = Arc::new(temp_path("concurrent_new_creates_once"));
+    let barrier = Arc::new(Barrier::new(THREADS));
+
+    let handles: Vec<_> = (0..THREADS)
+        .map(|i| {
+            let fp = Arc::clone(&fp);
+            let barrier = Arc::clone(&barrier);
+            thread::spawn(move || {
+                barrier.wait();
+                match FileSync::new(&fp, i, false) {
+                    Ok(file_sync) => Some(*file_sync.get()),
+                    Err(FileSyncError::FileAlreadyExists { .. }) => None,
+                    Err(e) => panic!("{e}"),
+                }
+            })
+        })
+        .collect();
+    let created: Vec<usize> = handles
+        .into_iter()
+        .filter_map(|handle| handle.join().unwrap())
+        .collect();
+
+    assert_eq!(created.len(), 1);
+    assert_eq!(
+        std::fs::read_to_string(fp.as_path()).unwrap(),
+        created[0].to_string()
+    );
+    std::fs::remove_file(fp.as_path()).unwrap();
+}
+
+#[test]
+fn concurrent_load_or_new_converges() {
+    let fp = Arc::new(temp_path("concurrent_load_or_new_converges"));
+    let barrier = Arc::new(Barrier::new(THREADS));
+
+    let handles: Vec<_> = (0..THREADS)
+        .map(|i| {
+            let fp = Arc::clone(&fp);
+            let barrier = Arc::clone(&barrier);
+            thread::spawn(move || {
+                barrier.wait();
+                let file_sync = FileSync::load_or_new(&fp, vec![i; 1000], false).unwrap();
+                file_sync.get().clone()
+            })
+        })
+        .collect();
+    let loaded: Vec<Vec<usize>> = handles
+        .into_iter()
+        .map(|handle| handle.join().unwrap())
+        .collect();
+
+    let on_disk: Vec<usize> =
+        serde_json::from_str(&std::fs::read_to_string(fp.as_path()).unwrap()).unwrap();
+    assert!(loaded.iter().all(|data| *data == on_disk));
+    std::fs::remove_file(fp.as_path()).unwrap();
+}
+
+#[test]
+fn failed_create_leaves_no_file() {
+    let fp = temp_path("failed_create_leaves_no_file");
+    // JSON object keys have to be strings, so this can't be serialized
+    let unserializable = HashMap::from([((1, 2), 3)]);
+
+    let result = FileSync::load_or_new(&fp, unserializable, false);
+
+    assert!(matches!(result, Err(FileSyncError::SerdeJsonError { .. })));
+    assert!(!fp.exists());
+    let start = Instant::now();
+    let file_sync = FileSync::load_or_new(&fp, HashMap::<(i32, i32), i32>::new(), false).unwrap();
+    assert!(start.elapsed() < Duration::from_millis(500));
+    assert!(file_sync.get().is_empty());
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+}
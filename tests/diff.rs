@@ -0,0 +1,45 @@
+mod common;
+
+use common::temp_path;
+use file_sync::FileSync;
+use serde_json::{json, Value};
+
+#[test]
+fn diff_with_disk_compares_to_file() {
+    let fp = temp_path("diff_with_disk_compares_to_file");
+    let file_sync = FileSync::new(&fp, json!({"a": 1, "b": [1, 2], "c/d": 1}), false).unwrap();
+    assert!(file_sync.diff_with_disk().unwrap().is_empty());
+
+    std::fs::write(&fp, r#"{"a": 2, "b": [1], "e": 1, "c/d": 1}"#).unwrap();
+    let diff = file_sync.diff_with_disk().unwrap();
+
+    assert_eq!(diff.added, ["/e"]);
+    assert_eq!(diff.removed, ["/b/1"]);
+    assert_eq!(diff.changed, ["/a"]);
+    // Only compares, the stored data stays as it was
+    assert_eq!(*file_sync.get(), json!({"a": 1, "b": [1, 2], "c/d": 1}));
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[test]
+fn diff_with_disk_applies_journal() {
+    let fp = temp_path("diff_with_disk_applies_journal");
+    let mut writer = FileSync::new(&fp, json!({"a": 1}), false).unwrap();
+    writer.set_journal(Some(1 << 20)).unwrap();
+    writer.modify(|value| value["a"] = json!(2)).unwrap();
+    let reader = FileSync::<Value>::load(&fp, false).unwrap();
+    let snapshot = std::fs::read(&fp).unwrap();
+
+    writer.modify(|value| value["b"] = json!(1)).unwrap();
+
+    // The change only went to the journal, which is read on top of the file
+    assert_eq!(std::fs::read(&fp).unwrap(), snapshot);
+    let diff = reader.diff_with_disk().unwrap();
+    assert_eq!(diff.added, ["/b"]);
+    assert!(diff.removed.is_empty() && diff.changed.is_empty());
+    drop(reader);
+    drop(writer);
+    std::fs::remove_file(&fp).unwrap();
+    std::fs::remove_file(fp.with_extension("json.journal")).unwrap();
+}
@@ -0,0 +1,140 @@
+mod common;
+
+use common::temp_path;
+use file_sync::encryption::EncryptionError;
+use file_sync::{Cipher, Encrypted, FileSync, FileSyncError, Json, Operation};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Not encryption, just enough to tell the format apart from plain JSON. The last byte is a sum of the others, so changes to the ciphertext are noticed
+#[derive(Debug)]
+struct Xor(u8);
+
+impl Xor {
+    fn tag(bytes: &[u8]) -> u8 {
+        bytes.iter().fold(0, |sum, b| sum.wrapping_add(*b))
+    }
+}
+
+impl Cipher for Xor {
+    fn key_id(&self) -> Vec<u8> {
+        vec![self.0]
+    }
+
+    fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, BoxError> {
+        let mut ciphertext: Vec<u8> = plaintext.iter().map(|b| b ^ self.0).collect();
+        ciphertext.push(Self::tag(&ciphertext));
+        Ok(ciphertext)
+    }
+
+    fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, BoxError> {
+        match ciphertext.split_last() {
+            Some((&tag, ciphertext)) if tag == Self::tag(ciphertext) => {
+                Ok(ciphertext.iter().map(|b| b ^ self.0).collect())
+            }
+            _ => Err("tag mismatch".into()),
+        }
+    }
+}
+
+#[test]
+fn round_trips() {
+    let fp = temp_path("encryption_round_trips");
+    let format = Encrypted::new(Xor(0x5a), Json);
+    drop(FileSync::new_with_format(&fp, "token".to_string(), false, format).unwrap());
+
+    let contents = std::fs::read(&fp).unwrap();
+    assert!(contents.starts_with(b"FSENC:5a\n"));
+    assert!(!contents.windows(5).any(|w| w == b"token"));
+    let file_sync =
+        FileSync::<String, _>::load_with_format(&fp, false, Encrypted::new(Xor(0x5a), Json))
+            .unwrap();
+    assert_eq!(file_sync.get(), "token");
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[test]
+fn wrong_key() {
+    let fp = temp_path("encryption_wrong_key");
+    let format = Encrypted::new(Xor(0x5a), Json);
+    drop(FileSync::new_with_format(&fp, "token".to_string(), false, format).unwrap());
+
+    let e = FileSync::<String, _>::load_with_format(&fp, false, Encrypted::new(Xor(0x33), Json))
+        .unwrap_err();
+
+    match e {
+        FileSyncError::WrongKey { op, fp: path } => {
+            assert_eq!(op, Operation::Load);
+            assert_eq!(path, fp);
+        }
+        e => panic!("unexpected error: {e}"),
+    }
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[test]
+fn tampered_file_fails_to_decrypt() {
+    let fp = temp_path("encryption_tampered_file_fails_to_decrypt");
+    let format = Encrypted::new(Xor(0x5a), Json);
+    drop(FileSync::new_with_format(&fp, "token".to_string(), false, format).unwrap());
+    let mut contents = std::fs::read(&fp).unwrap();
+    *contents.last_mut().unwrap() ^= 1;
+    std::fs::write(&fp, contents).unwrap();
+
+    let e = FileSync::<String, _>::load_with_format(&fp, false, Encrypted::new(Xor(0x5a), Json))
+        .unwrap_err();
+
+    match e {
+        FileSyncError::DecryptionFailed { op, fp: path, .. } => {
+            assert_eq!(op, Operation::Load);
+            assert_eq!(path, fp);
+        }
+        e => panic!("unexpected error: {e}"),
+    }
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[test]
+fn unencrypted_file() {
+    let fp = temp_path("encryption_unencrypted_file");
+    std::fs::write(&fp, "\"token\"").unwrap();
+
+    let e = FileSync::<String, _>::load_with_format(&fp, false, Encrypted::new(Xor(0x5a), Json))
+        .unwrap_err();
+    match e {
+        FileSyncError::FormatError { source, .. } => assert!(matches!(
+            source.downcast_ref::<EncryptionError>(),
+            Some(EncryptionError::NotEncrypted)
+        )),
+        e => panic!("unexpected error: {e}"),
+    }
+
+    let mut format = Encrypted::new(Xor(0x5a), Json);
+    format.allow_unencrypted = true;
+    let mut file_sync = FileSync::<String, _>::load_with_format(&fp, false, format).unwrap();
+    assert_eq!(file_sync.get(), "token");
+    file_sync.set("other token".to_string()).unwrap();
+    assert!(std::fs::read(&fp).unwrap().starts_with(b"FSENC:"));
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[test]
+fn refuses_plaintext_journal_and_history() {
+    let fp = temp_path("refuses_plaintext_journal_and_history");
+    let format = Encrypted::new(Xor(0x5a), Json);
+    let mut file_sync = FileSync::new_with_format(&fp, "token".to_string(), false, format).unwrap();
+
+    let journal = file_sync.set_journal(Some(1024));
+    let history = file_sync.set_history(10, true);
+
+    assert!(matches!(journal, Err(FileSyncError::Plaintext { .. })));
+    assert!(matches!(history, Err(FileSyncError::Plaintext { .. })));
+    assert!(file_sync.set_history(10, false).is_ok());
+    file_sync.set("other token".to_string()).unwrap();
+    let contents = std::fs::read(&fp).unwrap();
+    assert!(!contents.windows(5).any(|w| w == b"token"));
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+}
@@ -0,0 +1,100 @@
+mod common;
+
+use common::temp_path;
+use file_sync::{Checksummed, FileSync, FileSyncError, Json, Operation};
+use std::collections::HashMap;
+
+#[test]
+fn errors_are_owned() {
+    fn assert_owned<E: std::error::Error + Send + Sync + 'static>() {}
+    assert_owned::<FileSyncError>();
+}
+
+#[test]
+fn already_exists_has_path() {
+    let fp = temp_path("already_exists_has_path");
+    std::fs::write(&fp, "0").unwrap();
+
+    let e = FileSync::new(&fp, 1, false).unwrap_err();
+
+    assert!(matches!(&e, FileSyncError::FileAlreadyExists { fp: path } if *path == fp));
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[test]
+fn io_error_has_operation_and_path() {
+    let fp = temp_path("io_error_has_operation_and_path");
+
+    let e = FileSync::<i32>::load(&fp, false).unwrap_err();
+
+    match e {
+        FileSyncError::IoError {
+            op,
+            fp: path,
+            source,
+        } => {
+            assert_eq!(op, Operation::Load);
+            assert_eq!(path, fp);
+            assert_eq!(source.kind(), std::io::ErrorKind::NotFound);
+        }
+        e => panic!("unexpected error: {e}"),
+    }
+}
+
+#[test]
+fn serde_error_has_line_and_column() {
+    let fp = temp_path("serde_error_has_line_and_column");
+    std::fs::write(&fp, "[\n  1,\n  x\n]").unwrap();
+
+    let e = FileSync::<Vec<i32>>::load(&fp, false).unwrap_err();
+
+    match &e {
+        FileSyncError::SerdeJsonError {
+            op,
+            fp: path,
+            line,
+            column,
+            ..
+        } => {
+            assert_eq!(*op, Operation::Load);
+            assert_eq!(*path, fp);
+            assert_eq!((*line, *column), (3, 3));
+        }
+        e => panic!("unexpected error: {e}"),
+    }
+    assert!(e.to_string().contains("line 3, column 3"));
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[test]
+fn serialize_error_has_no_location() {
+    let fp = temp_path("serialize_error_has_no_location");
+    // JSON object keys have to be strings, so this can't be serialized
+    let unserializable = HashMap::from([((1, 2), 3)]);
+
+    let e = FileSync::new(&fp, unserializable, false).unwrap_err();
+
+    assert!(matches!(e, FileSyncError::SerdeJsonError { line: 0, .. }));
+    assert!(!e.to_string().contains("line"));
+}
+
+#[test]
+fn checksum_error_has_operation_and_path() {
+    let fp = temp_path("checksum_error_has_operation_and_path");
+    drop(FileSync::new_with_format(&fp, 1, false, Checksummed::new(Json)).unwrap());
+    let mut contents = std::fs::read(&fp).unwrap();
+    *contents.last_mut().unwrap() = b'2';
+    std::fs::write(&fp, contents).unwrap();
+
+    let e = FileSync::<i32, _>::load_with_format(&fp, false, Checksummed::new(Json)).unwrap_err();
+
+    match &e {
+        FileSyncError::ChecksumMismatch { op, fp: path, .. } => {
+            assert_eq!(*op, Operation::Load);
+            assert_eq!(*path, fp);
+        }
+        e => panic!("unexpected error: {e}"),
+    }
+    assert!(e.to_string().contains(&*fp.to_string_lossy()));
+    std::fs::remove_file(&fp).unwrap();
+}
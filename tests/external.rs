@@ -0,0 +1,123 @@
+mod common;
+
+use common::temp_path;
+use file_sync::{ExternalChangePolicy, FileSync, FileSyncError, WritePolicy};
+use std::time::{Duration, SystemTime};
+
+#[test]
+fn stale_after_external_write() {
+    let fp = temp_path("stale_after_external_write");
+    let file_sync = FileSync::new(&fp, 1, false).unwrap();
+    assert!(!file_sync.is_stale().unwrap());
+
+    std::fs::write(&fp, "2").unwrap();
+
+    assert!(file_sync.is_stale().unwrap());
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[test]
+fn not_stale_after_touch() {
+    let fp = temp_path("not_stale_after_touch");
+    let file_sync = FileSync::new(&fp, 1, false).unwrap();
+
+    let file = std::fs::File::options().write(true).open(&fp).unwrap();
+    file.set_modified(SystemTime::now() + Duration::from_secs(60))
+        .unwrap();
+    drop(file);
+
+    assert!(!file_sync.is_stale().unwrap());
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[test]
+fn stale_after_deletion() {
+    let fp = temp_path("stale_after_deletion");
+    let file_sync = FileSync::new(&fp, 1, false).unwrap();
+
+    std::fs::remove_file(&fp).unwrap();
+
+    assert!(file_sync.is_stale().unwrap());
+}
+
+#[test]
+fn reload_reads_external_changes() {
+    let fp = temp_path("reload_reads_external_changes");
+    let mut file_sync = FileSync::new(&fp, 1, false).unwrap();
+    std::fs::write(&fp, "2").unwrap();
+
+    file_sync.reload().unwrap();
+
+    assert_eq!(*file_sync.get(), 2);
+    assert!(!file_sync.is_stale().unwrap());
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[test]
+fn overwrite_policy_overwrites() {
+    let fp = temp_path("overwrite_policy_overwrites");
+    let mut file_sync = FileSync::new(&fp, 1, false).unwrap();
+    std::fs::write(&fp, "5").unwrap();
+
+    file_sync.modify(|n| *n += 1).unwrap();
+
+    assert_eq!(*file_sync.get(), 2);
+    assert_eq!(std::fs::read_to_string(&fp).unwrap(), "2");
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[test]
+fn error_policy_changes_nothing() {
+    let fp = temp_path("error_policy_changes_nothing");
+    let mut file_sync = FileSync::new(&fp, 1, false).unwrap();
+    file_sync.on_external_change = ExternalChangePolicy::Error;
+    file_sync.modify(|n| *n += 1).unwrap();
+    std::fs::write(&fp, "5").unwrap();
+
+    let e = file_sync.modify(|n| *n += 1).unwrap_err();
+
+    assert!(matches!(e, FileSyncError::ModifiedExternally { fp: path } if path == fp));
+    assert_eq!(*file_sync.get(), 2);
+    assert_eq!(std::fs::read_to_string(&fp).unwrap(), "5");
+    file_sync.on_external_change = ExternalChangePolicy::Overwrite;
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[test]
+fn reload_policy_applies_change_to_file() {
+    let fp = temp_path("reload_policy_applies_change_to_file");
+    let mut file_sync = FileSync::new(&fp, 1, false).unwrap();
+    file_sync.on_external_change = ExternalChangePolicy::Reload;
+    std::fs::write(&fp, "5").unwrap();
+
+    file_sync.modify(|n| *n += 1).unwrap();
+
+    assert_eq!(*file_sync.get(), 6);
+    assert_eq!(std::fs::read_to_string(&fp).unwrap(), "6");
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[test]
+fn reload_policy_keeps_unwritten_changes() {
+    let fp = temp_path("reload_policy_keeps_unwritten_changes");
+    let mut file_sync = FileSync::new(&fp, 1, false).unwrap();
+    file_sync.on_external_change = ExternalChangePolicy::Reload;
+    file_sync.write_policy = WritePolicy::Manual;
+    file_sync.set(2).unwrap();
+    std::fs::write(&fp, "5").unwrap();
+
+    let e = file_sync.flush().unwrap_err();
+
+    assert!(matches!(e, FileSyncError::ModifiedExternally { .. }));
+    assert_eq!(*file_sync.get(), 2);
+    assert_eq!(std::fs::read_to_string(&fp).unwrap(), "5");
+    file_sync.on_external_change = ExternalChangePolicy::Overwrite;
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+}
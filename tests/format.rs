@@ -0,0 +1,114 @@
+mod common;
+
+use common::temp_path;
+use file_sync::{FileSync, FileSyncError, Format, Operation};
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+
+/// JSON written as hex, so it can't be read as JSON by accident
+#[derive(Debug)]
+struct Hex;
+
+#[derive(thiserror::Error, Debug)]
+enum HexError {
+    #[error("Invalid hex")]
+    InvalidHex,
+    #[error("IO error")]
+    Io(#[from] std::io::Error),
+    #[error("JSON error")]
+    Json(#[from] serde_json::Error),
+}
+
+impl Format for Hex {
+    type Error = HexError;
+
+    fn serialize<W, T>(&self, mut writer: W, value: &T, _pretty: bool) -> Result<(), Self::Error>
+    where
+        W: Write,
+        T: Serialize + ?Sized,
+    {
+        for b in serde_json::to_vec(value)? {
+            write!(writer, "{b:02x}")?;
+        }
+        Ok(())
+    }
+
+    fn deserialize<R, T>(&self, mut reader: R) -> Result<T, Self::Error>
+    where
+        R: Read,
+        T: DeserializeOwned,
+    {
+        let mut hex = String::new();
+        reader.read_to_string(&mut hex)?;
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|i| {
+                hex.get(i..i + 2)
+                    .and_then(|b| u8::from_str_radix(b, 16).ok())
+                    .ok_or(HexError::InvalidHex)
+            })
+            .collect::<Result<Vec<u8>, _>>()?;
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+}
+
+#[test]
+fn round_trips_through_other_format() {
+    let fp = temp_path("round_trips_through_other_format");
+    let mut file_sync = FileSync::new_with_format(&fp, vec![1, 2], false, Hex).unwrap();
+    file_sync.modify(|v| v.push(3)).unwrap();
+    drop(file_sync);
+
+    assert_eq!(std::fs::read_to_string(&fp).unwrap(), "5b312c322c335d");
+    let file_sync = FileSync::<Vec<i32>, _>::load_with_format(&fp, false, Hex).unwrap();
+    assert_eq!(*file_sync.get(), [1, 2, 3]);
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[test]
+fn format_error_on_load_has_operation_and_path() {
+    let fp = temp_path("format_error_on_load_has_operation_and_path");
+    std::fs::write(&fp, "not hex").unwrap();
+
+    let e = FileSync::<Vec<i32>, _>::load_with_format(&fp, false, Hex).unwrap_err();
+
+    match &e {
+        FileSyncError::FormatError {
+            op,
+            fp: path,
+            source,
+        } => {
+            assert_eq!(*op, Operation::Load);
+            assert_eq!(*path, fp);
+            assert!(matches!(
+                source.downcast_ref::<HexError>(),
+                Some(HexError::InvalidHex)
+            ));
+        }
+        e => panic!("unexpected error: {e}"),
+    }
+    assert!(e.to_string().contains(&*fp.to_string_lossy()));
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[test]
+fn format_error_on_write_has_operation_and_path() {
+    let fp = temp_path("format_error_on_write_has_operation_and_path");
+    let mut file_sync = FileSync::new_with_format(&fp, HashMap::new(), false, Hex).unwrap();
+
+    // JSON object keys have to be strings, so this can't be serialized
+    let e = file_sync.set(HashMap::from([((1, 2), 3)])).unwrap_err();
+
+    match e {
+        FileSyncError::FormatError { op, fp: path, .. } => {
+            assert_eq!(op, Operation::Write);
+            assert_eq!(path, fp);
+        }
+        e => panic!("unexpected error: {e}"),
+    }
+    assert!(file_sync.get().is_empty());
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+}
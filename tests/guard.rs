@@ -0,0 +1,141 @@
+mod common;
+
+use common::temp_path;
+use file_sync::{FileSync, FileSyncError, WritePolicy};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[test]
+fn panic_in_modify_writes_nothing() {
+    let fp = temp_path("panic_in_modify_writes_nothing");
+    let mut file_sync = FileSync::new(&fp, vec![1, 2, 3], false).unwrap();
+
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        file_sync
+            .modify(|v| {
+                v.clear();
+                v.push(99);
+                panic!("cut short");
+            })
+            .unwrap();
+    }));
+
+    assert!(result.is_err());
+    assert_eq!(*file_sync.get(), vec![1, 2, 3]);
+    assert_eq!(std::fs::read_to_string(&fp).unwrap(), "[1,2,3]");
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[test]
+fn panic_in_deferred_modify_poisons() {
+    let fp = temp_path("panic_in_deferred_modify_poisons");
+    let mut file_sync = FileSync::new(&fp, vec![1, 2, 3], false).unwrap();
+    file_sync.write_policy = WritePolicy::Manual;
+
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        file_sync
+            .modify(|v| {
+                v.clear();
+                panic!("cut short");
+            })
+            .unwrap();
+    }));
+
+    assert!(result.is_err());
+    assert!(file_sync.is_poisoned());
+    assert!(matches!(
+        file_sync.flush(),
+        Err(FileSyncError::Poisoned { .. })
+    ));
+    file_sync.write_policy = WritePolicy::Immediate;
+    assert!(matches!(
+        file_sync.set(vec![4]),
+        Err(FileSyncError::Poisoned { .. })
+    ));
+    assert_eq!(std::fs::read_to_string(&fp).unwrap(), "[1,2,3]");
+
+    file_sync.reload().unwrap();
+    assert!(!file_sync.is_poisoned());
+    file_sync.set(vec![4]).unwrap();
+    assert_eq!(std::fs::read_to_string(&fp).unwrap(), "[4]");
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[test]
+fn poisoned_file_sync_writes_nothing_on_drop() {
+    let fp = temp_path("poisoned_file_sync_writes_nothing_on_drop");
+    let mut file_sync = FileSync::new(&fp, vec![1, 2, 3], false).unwrap();
+    file_sync.write_policy = WritePolicy::Manual;
+
+    let _ = catch_unwind(AssertUnwindSafe(|| {
+        let mut guard = file_sync.borrow_mut().unwrap();
+        guard.clear();
+        panic!("cut short");
+    }));
+    drop(file_sync);
+
+    assert_eq!(std::fs::read_to_string(&fp).unwrap(), "[1,2,3]");
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[test]
+fn clear_poison_allows_writing() {
+    let fp = temp_path("clear_poison_allows_writing");
+    let mut file_sync = FileSync::new(&fp, vec![1, 2, 3], false).unwrap();
+    file_sync.write_policy = WritePolicy::Manual;
+
+    let _ = catch_unwind(AssertUnwindSafe(|| {
+        file_sync
+            .modify(|v| {
+                v.pop();
+                panic!("cut short");
+            })
+            .unwrap();
+    }));
+    file_sync.clear_poison();
+    file_sync.flush().unwrap();
+
+    assert_eq!(std::fs::read_to_string(&fp).unwrap(), "[1,2]");
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+}
+
+/// Counts how many times it is serialized
+struct Counted(u32);
+
+static SERIALIZATIONS: AtomicUsize = AtomicUsize::new(0);
+
+impl Serialize for Counted {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        SERIALIZATIONS.fetch_add(1, Ordering::SeqCst);
+        self.0.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Counted {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        u32::deserialize(deserializer).map(Counted)
+    }
+}
+
+#[test]
+fn deferred_modify_does_not_serialize() {
+    let fp = temp_path("deferred_modify_does_not_serialize");
+    let mut file_sync = FileSync::new(&fp, Counted(0), false).unwrap();
+    file_sync.write_policy = WritePolicy::Manual;
+
+    let before = SERIALIZATIONS.load(Ordering::SeqCst);
+    for _ in 0..100 {
+        file_sync.modify(|counted| counted.0 += 1).unwrap();
+    }
+    assert_eq!(SERIALIZATIONS.load(Ordering::SeqCst), before);
+
+    file_sync.flush().unwrap();
+    assert_eq!(SERIALIZATIONS.load(Ordering::SeqCst), before + 1);
+    assert_eq!(std::fs::read_to_string(&fp).unwrap(), "100");
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+}
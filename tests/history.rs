@@ -0,0 +1,132 @@
+mod common;
+
+use common::temp_path;
+use file_sync::FileSync;
+use std::path::{Path, PathBuf};
+
+fn history_path(fp: &Path) -> PathBuf {
+    let mut name = fp.file_name().unwrap().to_os_string();
+    name.push(".history");
+    fp.with_file_name(name)
+}
+
+#[test]
+fn undo_and_redo() {
+    let fp = temp_path("undo_and_redo");
+    let mut file_sync = FileSync::new(&fp, 0, false).unwrap();
+    file_sync.set_history(10, false).unwrap();
+    assert!(!file_sync.can_undo());
+    assert!(!file_sync.undo().unwrap());
+
+    file_sync.set(1).unwrap();
+    file_sync.set(2).unwrap();
+
+    assert!(file_sync.undo().unwrap());
+    assert_eq!(*file_sync.get(), 1);
+    assert_eq!(std::fs::read_to_string(&fp).unwrap(), "1");
+    assert!(file_sync.undo().unwrap());
+    assert_eq!(*file_sync.get(), 0);
+    assert!(!file_sync.can_undo());
+
+    assert!(file_sync.redo().unwrap());
+    assert_eq!(*file_sync.get(), 1);
+    assert!(file_sync.redo().unwrap());
+    assert_eq!(*file_sync.get(), 2);
+    assert!(!file_sync.redo().unwrap());
+    assert_eq!(std::fs::read_to_string(&fp).unwrap(), "2");
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[test]
+fn change_forgets_redo() {
+    let fp = temp_path("change_forgets_redo");
+    let mut file_sync = FileSync::new(&fp, 0, false).unwrap();
+    file_sync.set_history(10, false).unwrap();
+
+    file_sync.set(1).unwrap();
+    file_sync.undo().unwrap();
+    assert!(file_sync.can_redo());
+    file_sync.set(2).unwrap();
+
+    assert!(!file_sync.can_redo());
+    file_sync.undo().unwrap();
+    assert_eq!(*file_sync.get(), 0);
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[test]
+fn depth_limits_undo() {
+    let fp = temp_path("depth_limits_undo");
+    let mut file_sync = FileSync::new(&fp, 0, false).unwrap();
+    file_sync.set_history(2, false).unwrap();
+
+    for n in 1..=5 {
+        file_sync.set(n).unwrap();
+    }
+
+    assert!(file_sync.undo().unwrap());
+    assert!(file_sync.undo().unwrap());
+    assert!(!file_sync.undo().unwrap());
+    assert_eq!(*file_sync.get(), 3);
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[test]
+fn zero_depth_turns_history_off() {
+    let fp = temp_path("zero_depth_turns_history_off");
+    let mut file_sync = FileSync::new(&fp, 0, false).unwrap();
+    file_sync.set_history(10, false).unwrap();
+    file_sync.set(1).unwrap();
+
+    file_sync.set_history(0, false).unwrap();
+    file_sync.set(2).unwrap();
+
+    assert!(!file_sync.can_undo());
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[test]
+fn persisted_history_survives_reopening() {
+    let fp = temp_path("persisted_history_survives_reopening");
+    let mut file_sync = FileSync::new(&fp, 0, false).unwrap();
+    file_sync.set_history(10, true).unwrap();
+    file_sync.set(1).unwrap();
+    file_sync.set(2).unwrap();
+    file_sync.undo().unwrap();
+    drop(file_sync);
+
+    let mut file_sync = FileSync::<i32>::load(&fp, false).unwrap();
+    file_sync.set_history(10, true).unwrap();
+
+    assert!(file_sync.redo().unwrap());
+    assert_eq!(*file_sync.get(), 2);
+    assert!(file_sync.undo().unwrap());
+    assert!(file_sync.undo().unwrap());
+    assert_eq!(*file_sync.get(), 0);
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+    std::fs::remove_file(history_path(&fp)).unwrap();
+}
+
+#[test]
+fn failing_to_persist_history_is_not_an_error() {
+    let fp = temp_path("failing_to_persist_history_is_not_an_error");
+    let mut file_sync = FileSync::new(&fp, 0, false).unwrap();
+    file_sync.set_history(10, true).unwrap();
+    // A directory can't be replaced by the history file
+    std::fs::create_dir(history_path(&fp)).unwrap();
+
+    file_sync.set(5).unwrap();
+
+    assert_eq!(*file_sync.get(), 5);
+    assert_eq!(std::fs::read_to_string(&fp).unwrap(), "5");
+    assert!(file_sync.undo().unwrap());
+    assert_eq!(*file_sync.get(), 0);
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+    std::fs::remove_dir(history_path(&fp)).unwrap();
+}
@@ -0,0 +1,124 @@
+mod common;
+
+use common::temp_path;
+use file_sync::{FileSync, FileSyncError, LockMode, LockWait, Locking, WritePolicy};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+const EXCLUSIVE: Locking = Locking {
+    mode: LockMode::Exclusive,
+    wait: LockWait::Try,
+};
+
+fn lock_path(fp: &Path) -> PathBuf {
+    let mut name = fp.file_name().unwrap().to_os_string();
+    name.push(".lock");
+    fp.with_file_name(name)
+}
+
+fn remove(fp: &Path) {
+    std::fs::remove_file(fp).unwrap();
+    std::fs::remove_file(lock_path(fp)).unwrap();
+}
+
+#[test]
+fn exclusive_lock_keeps_others_out() {
+    let fp = temp_path("exclusive_lock_keeps_others_out");
+    let mut holder = FileSync::new(&fp, 1, false).unwrap();
+    holder.set_locking(Some(EXCLUSIVE)).unwrap();
+    let mut other = FileSync::<i32>::load(&fp, false).unwrap();
+
+    let e = other.set_locking(Some(EXCLUSIVE)).unwrap_err();
+
+    assert!(matches!(e, FileSyncError::Locked { lock_fp } if lock_fp == lock_path(&fp)));
+    drop(holder);
+    other.set_locking(Some(EXCLUSIVE)).unwrap();
+    drop(other);
+    remove(&fp);
+}
+
+#[test]
+fn timeout_gives_up() {
+    let fp = temp_path("timeout_gives_up");
+    let mut holder = FileSync::new(&fp, 1, false).unwrap();
+    holder.set_locking(Some(EXCLUSIVE)).unwrap();
+    let mut other = FileSync::<i32>::load(&fp, false).unwrap();
+    let timeout = Duration::from_millis(100);
+
+    let start = Instant::now();
+    let e = other
+        .set_locking(Some(Locking {
+            mode: LockMode::Exclusive,
+            wait: LockWait::Timeout(timeout),
+        }))
+        .unwrap_err();
+
+    assert!(start.elapsed() >= timeout);
+    assert!(matches!(e, FileSyncError::Locked { .. }));
+    drop(holder);
+    drop(other);
+    remove(&fp);
+}
+
+#[test]
+fn shared_exclusive_locks_around_writes() {
+    let fp = temp_path("shared_exclusive_locks_around_writes");
+    let mut holder = FileSync::new(&fp, 1, false).unwrap();
+    let mut other = FileSync::<i32>::load(&fp, false).unwrap();
+    other
+        .set_locking(Some(Locking {
+            mode: LockMode::SharedExclusive,
+            wait: LockWait::Try,
+        }))
+        .unwrap();
+    holder.set_locking(Some(EXCLUSIVE)).unwrap();
+
+    assert!(matches!(other.set(2), Err(FileSyncError::Locked { .. })));
+    assert!(matches!(other.reload(), Err(FileSyncError::Locked { .. })));
+    assert_eq!(*other.get(), 1);
+    assert_eq!(std::fs::read_to_string(&fp).unwrap(), "1");
+
+    drop(holder);
+    other.set(2).unwrap();
+    assert_eq!(std::fs::read_to_string(&fp).unwrap(), "2");
+    drop(other);
+    remove(&fp);
+}
+
+#[test]
+fn exclusive_lock_reads_changes_made_before_it() {
+    let fp = temp_path("exclusive_lock_reads_changes_made_before_it");
+    let mut file_sync = FileSync::new(&fp, 1, false).unwrap();
+    std::fs::write(&fp, "5").unwrap();
+
+    file_sync.set_locking(Some(EXCLUSIVE)).unwrap();
+
+    assert_eq!(*file_sync.get(), 5);
+    drop(file_sync);
+    remove(&fp);
+}
+
+#[test]
+fn exclusive_lock_keeps_unwritten_changes() {
+    let fp = temp_path("exclusive_lock_keeps_unwritten_changes");
+    let mut file_sync = FileSync::new(&fp, 1, false).unwrap();
+    file_sync.write_policy = WritePolicy::Manual;
+    file_sync.set(2).unwrap();
+
+    file_sync.set_locking(Some(EXCLUSIVE)).unwrap();
+    assert_eq!(*file_sync.get(), 2);
+    file_sync.set_locking(None).unwrap();
+
+    std::fs::write(&fp, "5").unwrap();
+    let e = file_sync.set_locking(Some(EXCLUSIVE)).unwrap_err();
+
+    assert!(matches!(e, FileSyncError::ModifiedExternally { .. }));
+    assert_eq!(*file_sync.get(), 2);
+    assert!(file_sync.is_dirty());
+    // The lock was released again
+    let mut other = FileSync::<i32>::load(&fp, false).unwrap();
+    other.set_locking(Some(EXCLUSIVE)).unwrap();
+    drop(other);
+    drop(file_sync);
+    remove(&fp);
+}
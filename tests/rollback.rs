@@ -0,0 +1,107 @@
+mod common;
+
+use common::temp_path;
+use file_sync::{FileSync, FileSyncError, Operation};
+use std::collections::HashMap;
+
+#[test]
+fn failed_serialize_restores_data() {
+    let fp = temp_path("failed_serialize_restores_data");
+    // JSON object keys have to be strings, so a map with tuple keys can only be serialized while it is empty
+    let mut file_sync = FileSync::new(&fp, HashMap::<(i32, i32), i32>::new(), false).unwrap();
+
+    let set = file_sync.set(HashMap::from([((1, 2), 3)]));
+    let modify = file_sync.modify(|map| {
+        map.insert((4, 5), 6);
+    });
+
+    assert!(matches!(
+        set,
+        Err(FileSyncError::SerdeJsonError {
+            op: Operation::Write,
+            ..
+        })
+    ));
+    assert!(matches!(
+        modify,
+        Err(FileSyncError::SerdeJsonError {
+            op: Operation::Write,
+            ..
+        })
+    ));
+    assert!(file_sync.get().is_empty());
+    assert_eq!(std::fs::read_to_string(&fp).unwrap(), "{}");
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[test]
+fn failed_write_restores_data_and_leaves_file_intact() {
+    // Most filesystems limit names to 255 bytes. The temporary file of an atomic write has at least 9 more bytes in its name, and the journal 8,
+    // so with a name of 247 bytes the file and its journal can be read but not written
+    let taken = temp_path("").file_name().unwrap().len();
+    let fp = temp_path(&"x".repeat(247 - taken));
+    assert_eq!(fp.file_name().unwrap().len(), 247);
+    std::fs::write(&fp, "[1,2,3]").unwrap();
+    let mut file_sync = FileSync::<Vec<i32>>::load(&fp, false).unwrap();
+
+    let e = file_sync.modify(|v| v.push(4)).unwrap_err();
+
+    match e {
+        FileSyncError::IoError { op, fp: path, .. } => {
+            assert_eq!(op, Operation::Write);
+            assert_eq!(path, fp);
+        }
+        e => panic!("unexpected error: {e}"),
+    }
+    assert_eq!(*file_sync.get(), [1, 2, 3]);
+    assert_eq!(std::fs::read_to_string(&fp).unwrap(), "[1,2,3]");
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[test]
+fn rollback_failed_when_file_can_not_be_restored() {
+    let dir = temp_path("rollback_failed_dir");
+    let moved = temp_path("rollback_failed_moved");
+    let _ = std::fs::remove_dir_all(&dir);
+    let _ = std::fs::remove_dir_all(&moved);
+    std::fs::create_dir(&dir).unwrap();
+    let fp = dir.join("data.json");
+    let mut file_sync = FileSync::new(&fp, 1, false).unwrap();
+    // Neither the new data nor the old data can be written where the file was
+    std::fs::rename(&dir, &moved).unwrap();
+
+    let e = file_sync.set(2).unwrap_err();
+
+    match e {
+        FileSyncError::RollbackFailed {
+            error,
+            rollback_error,
+        } => {
+            assert!(matches!(
+                *error,
+                FileSyncError::IoError {
+                    op: Operation::Write,
+                    ..
+                }
+            ));
+            assert!(matches!(
+                *rollback_error,
+                FileSyncError::IoError {
+                    op: Operation::Write,
+                    ..
+                }
+            ));
+        }
+        e => panic!("unexpected error: {e}"),
+    }
+    assert_eq!(*file_sync.get(), 1);
+    assert_eq!(
+        std::fs::read_to_string(moved.join("data.json")).unwrap(),
+        "1"
+    );
+    std::fs::rename(&moved, &dir).unwrap();
+    drop(file_sync);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,35 @@
+mod common;
+
+use common::temp_path;
+use file_sync::FileSync;
+use std::thread;
+
+#[test]
+fn concurrent_modifies_all_reach_the_file() {
+    let fp = temp_path("concurrent_modifies_all_reach_the_file");
+    let shared = FileSync::new(&fp, Vec::<usize>::new(), false)
+        .unwrap()
+        .into_shared();
+
+    let handles: Vec<_> = (0..4)
+        .map(|i| {
+            let shared = shared.clone();
+            thread::spawn(move || {
+                for n in 0..50 {
+                    shared.modify(|v| v.push(i * 100 + n)).unwrap();
+                    assert!(!shared.read().is_empty());
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    let on_disk: Vec<usize> = serde_json::from_str(&std::fs::read_to_string(&fp).unwrap()).unwrap();
+    assert_eq!(on_disk.len(), 200);
+    assert_eq!(*shared.read(), on_disk);
+    assert!(!shared.write().is_dirty());
+    drop(shared);
+    std::fs::remove_file(&fp).unwrap();
+}
@@ -0,0 +1,85 @@
+mod common;
+
+use common::temp_path;
+use file_sync::{FileSync, FileSyncError, WritePolicy};
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+#[error("Too big: {0}")]
+struct TooBig(i32);
+
+fn push_small(v: &mut Vec<i32>, n: i32) -> Result<usize, TooBig> {
+    v.push(n);
+    if n > 10 {
+        return Err(TooBig(n));
+    }
+    Ok(v.len())
+}
+
+#[test]
+fn ok_writes_and_returns_value() {
+    let fp = temp_path("ok_writes_and_returns_value");
+    let mut file_sync = FileSync::new(&fp, vec![1], false).unwrap();
+
+    let len = file_sync.try_modify(|v| push_small(v, 2)).unwrap();
+
+    assert_eq!(len, 2);
+    assert_eq!(*file_sync.get(), [1, 2]);
+    assert_eq!(std::fs::read_to_string(&fp).unwrap(), "[1,2]");
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[test]
+fn err_restores_data_and_leaves_file_untouched() {
+    let fp = temp_path("err_restores_data_and_leaves_file_untouched");
+    let mut file_sync = FileSync::new(&fp, vec![1], false).unwrap();
+    let modified = std::fs::metadata(&fp).unwrap().modified().unwrap();
+
+    let e = file_sync.try_modify(|v| push_small(v, 20)).unwrap_err();
+
+    assert!(matches!(e, FileSyncError::ClosureError(_)));
+    assert_eq!(*file_sync.get(), [1]);
+    assert!(!file_sync.is_dirty());
+    assert_eq!(std::fs::read_to_string(&fp).unwrap(), "[1]");
+    assert_eq!(
+        std::fs::metadata(&fp).unwrap().modified().unwrap(),
+        modified
+    );
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[test]
+fn err_restores_data_when_write_is_deferred() {
+    let fp = temp_path("err_restores_data_when_write_is_deferred");
+    let mut file_sync = FileSync::new(&fp, vec![1], false).unwrap();
+    file_sync.write_policy = WritePolicy::Manual;
+    file_sync.try_modify(|v| push_small(v, 2)).unwrap();
+
+    file_sync.try_modify(|v| push_small(v, 20)).unwrap_err();
+
+    // The earlier change is kept and still waits to be written
+    assert_eq!(*file_sync.get(), [1, 2]);
+    assert!(file_sync.is_dirty());
+    file_sync.flush().unwrap();
+    assert_eq!(std::fs::read_to_string(&fp).unwrap(), "[1,2]");
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[test]
+fn error_can_be_downcast() {
+    let fp = temp_path("error_can_be_downcast");
+    let mut file_sync = FileSync::new(&fp, Vec::new(), false).unwrap();
+
+    let e = file_sync.try_modify(|v| push_small(v, 11)).unwrap_err();
+
+    match e {
+        FileSyncError::ClosureError(source) => {
+            assert_eq!(*source.downcast::<TooBig>().unwrap(), TooBig(11));
+        }
+        e => panic!("unexpected error: {e}"),
+    }
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+}
@@ -0,0 +1,133 @@
+mod common;
+
+use common::temp_path;
+use file_sync::versioning::VersionedError;
+use file_sync::{FileSync, FileSyncError, Json, Migrations, Versioned};
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+
+/// Version 0 is a bare name, version 1 wraps it in an object, version 2 adds a count
+fn migrations() -> Migrations {
+    Migrations::new(2)
+        .add(0, |name| Ok(json!({ "name": name })))
+        .add(1, |mut value| {
+            value["count"] = json!(0);
+            Ok(value)
+        })
+}
+
+fn backup_path(fp: &Path, version: u32) -> PathBuf {
+    let mut name = fp.file_name().unwrap().to_os_string();
+    name.push(format!(".v{version}.bak"));
+    fp.with_file_name(name)
+}
+
+#[test]
+fn runs_migration_chain_and_writes_back() {
+    let fp = temp_path("runs_migration_chain_and_writes_back");
+    std::fs::write(&fp, r#""alice""#).unwrap();
+
+    let file_sync =
+        FileSync::<Value, _>::load_with_format(&fp, false, Versioned::new(Json, migrations()))
+            .unwrap();
+
+    assert_eq!(*file_sync.get(), json!({"name": "alice", "count": 0}));
+    let on_disk: Value = serde_json::from_slice(&std::fs::read(&fp).unwrap()).unwrap();
+    assert_eq!(
+        on_disk,
+        json!({"file_sync_version": 2, "data": {"name": "alice", "count": 0}})
+    );
+    assert_eq!(
+        std::fs::read_to_string(backup_path(&fp, 0)).unwrap(),
+        r#""alice""#
+    );
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+    std::fs::remove_file(backup_path(&fp, 0)).unwrap();
+}
+
+#[test]
+fn migrates_from_middle_of_chain() {
+    let fp = temp_path("migrates_from_middle_of_chain");
+    std::fs::write(&fp, r#"{"file_sync_version":1,"data":{"name":"bob"}}"#).unwrap();
+    let mut format = Versioned::new(Json, migrations());
+    format.backup = false;
+
+    let file_sync = FileSync::<Value, _>::load_with_format(&fp, false, format).unwrap();
+
+    assert_eq!(*file_sync.get(), json!({"name": "bob", "count": 0}));
+    assert!(!backup_path(&fp, 1).exists());
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[test]
+fn write_back_can_wait_for_next_write() {
+    let fp = temp_path("write_back_can_wait_for_next_write");
+    std::fs::write(&fp, r#""carol""#).unwrap();
+    let mut format = Versioned::new(Json, migrations());
+    format.write_back = false;
+
+    let mut file_sync = FileSync::<Value, _>::load_with_format(&fp, false, format).unwrap();
+
+    assert_eq!(std::fs::read_to_string(&fp).unwrap(), r#""carol""#);
+    assert!(!backup_path(&fp, 0).exists());
+    file_sync.modify(|value| value["count"] = json!(1)).unwrap();
+    let on_disk: Value = serde_json::from_slice(&std::fs::read(&fp).unwrap()).unwrap();
+    assert_eq!(on_disk["file_sync_version"], 2);
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[test]
+fn current_version_is_not_migrated() {
+    let fp = temp_path("current_version_is_not_migrated");
+    let contents = r#"{"file_sync_version":2,"data":{"name":"dave","count":3}}"#;
+    std::fs::write(&fp, contents).unwrap();
+
+    let file_sync =
+        FileSync::<Value, _>::load_with_format(&fp, false, Versioned::new(Json, migrations()))
+            .unwrap();
+
+    assert_eq!(*file_sync.get(), json!({"name": "dave", "count": 3}));
+    assert_eq!(std::fs::read_to_string(&fp).unwrap(), contents);
+    assert!(!backup_path(&fp, 2).exists());
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[test]
+fn missing_migration_is_an_error() {
+    let fp = temp_path("missing_migration_is_an_error");
+    std::fs::write(&fp, r#""erin""#).unwrap();
+    let migrations = Migrations::new(2).add(0, |name| Ok(json!({ "name": name })));
+
+    let e = FileSync::<Value, _>::load_with_format(&fp, false, Versioned::new(Json, migrations))
+        .unwrap_err();
+
+    match e {
+        FileSyncError::FormatError { source, .. } => assert!(matches!(
+            source.downcast_ref::<VersionedError<serde_json::Error>>(),
+            Some(VersionedError::MissingMigration { from: 1 })
+        )),
+        e => panic!("unexpected error: {e}"),
+    }
+    assert_eq!(std::fs::read_to_string(&fp).unwrap(), r#""erin""#);
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[test]
+fn data_shaped_like_an_old_envelope_is_data() {
+    let fp = temp_path("data_shaped_like_an_old_envelope_is_data");
+    std::fs::write(&fp, r#"{"version":1,"data":"x"}"#).unwrap();
+    let migrations = Migrations::new(1).add(0, |value| Ok(json!([value])));
+
+    let file_sync =
+        FileSync::<Value, _>::load_with_format(&fp, false, Versioned::new(Json, migrations))
+            .unwrap();
+
+    assert_eq!(*file_sync.get(), json!([{"version": 1, "data": "x"}]));
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+    std::fs::remove_file(backup_path(&fp, 0)).unwrap();
+}
@@ -0,0 +1,53 @@
+mod common;
+
+use common::temp_path;
+use file_sync::{ExternalChangePolicy, FileSync, FileSyncError, WatchEvent, WritePolicy};
+use std::sync::mpsc;
+use std::time::Duration;
+
+const INTERVAL: Duration = Duration::from_millis(10);
+
+#[test]
+fn keeps_unwritten_changes() {
+    let fp = temp_path("keeps_unwritten_changes");
+    let mut file_sync = FileSync::new(&fp, 1, false).unwrap();
+    file_sync.write_policy = WritePolicy::Manual;
+    file_sync.set(2).unwrap();
+    let (events, received) = mpsc::channel();
+    let watched = file_sync.watch(INTERVAL, move |event| {
+        let _ = events.send(matches!(
+            event,
+            WatchEvent::Error(FileSyncError::ModifiedExternally { .. })
+        ));
+    });
+
+    std::fs::write(&fp, "7").unwrap();
+
+    assert!(received.recv_timeout(Duration::from_secs(5)).unwrap());
+    assert!(received.recv_timeout(INTERVAL * 10).is_err());
+    assert_eq!(*watched.lock().get(), 2);
+    assert!(watched.lock().is_dirty());
+    drop(watched);
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[test]
+fn merges_unwritten_changes() {
+    let fp = temp_path("merges_unwritten_changes");
+    let mut file_sync = FileSync::new(&fp, serde_json::json!({"a": 1, "b": 1}), false).unwrap();
+    file_sync.write_policy = WritePolicy::Manual;
+    file_sync.on_external_change = ExternalChangePolicy::Merge;
+    file_sync.modify(|v| v["a"] = 2.into()).unwrap();
+    let (events, received) = mpsc::channel();
+    let watched = file_sync.watch(INTERVAL, move |event| {
+        let _ = events.send(matches!(event, WatchEvent::Changed { .. }));
+    });
+
+    std::fs::write(&fp, r#"{"a":1,"b":3}"#).unwrap();
+
+    assert!(received.recv_timeout(Duration::from_secs(5)).unwrap());
+    assert_eq!(*watched.lock().get(), serde_json::json!({"a": 2, "b": 3}));
+    assert!(watched.lock().is_dirty());
+    drop(watched);
+    std::fs::remove_file(&fp).unwrap();
+}
@@ -0,0 +1,98 @@
+mod common;
+
+use common::temp_path;
+use file_sync::{FileSync, WritePolicy};
+use std::num::NonZeroUsize;
+use std::thread;
+use std::time::Duration;
+
+fn on_disk(fp: &std::path::Path) -> String {
+    std::fs::read_to_string(fp).unwrap()
+}
+
+#[test]
+fn throttle_holds_changes_within_interval() {
+    let fp = temp_path("throttle_holds_changes_within_interval");
+    let mut file_sync = FileSync::new(&fp, 0, false).unwrap();
+    file_sync.write_policy = WritePolicy::Throttle(Duration::from_millis(200));
+
+    // Creating the file counts as a write
+    file_sync.set(1).unwrap();
+    file_sync.set(2).unwrap();
+    assert_eq!(on_disk(&fp), "0");
+    assert!(file_sync.is_dirty());
+
+    // Nothing writes a held change in the background
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!(on_disk(&fp), "0");
+
+    file_sync.set(3).unwrap();
+    assert_eq!(on_disk(&fp), "3");
+    assert!(!file_sync.is_dirty());
+    file_sync.set(4).unwrap();
+    assert_eq!(on_disk(&fp), "3");
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[test]
+fn every_n_writes_on_nth_change() {
+    let fp = temp_path("every_n_writes_on_nth_change");
+    let mut file_sync = FileSync::new(&fp, 0, false).unwrap();
+    file_sync.write_policy = WritePolicy::EveryN(NonZeroUsize::new(3).unwrap());
+
+    file_sync.set(1).unwrap();
+    file_sync.set(2).unwrap();
+    assert_eq!(on_disk(&fp), "0");
+    file_sync.set(3).unwrap();
+    assert_eq!(on_disk(&fp), "3");
+    file_sync.set(4).unwrap();
+    assert_eq!(on_disk(&fp), "3");
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[test]
+fn manual_only_writes_on_flush() {
+    let fp = temp_path("manual_only_writes_on_flush");
+    let mut file_sync = FileSync::new(&fp, 0, false).unwrap();
+    file_sync.write_policy = WritePolicy::Manual;
+
+    assert!(!file_sync.is_dirty());
+    file_sync.set(1).unwrap();
+    file_sync.modify(|n| *n += 1).unwrap();
+    assert!(file_sync.is_dirty());
+    assert_eq!(on_disk(&fp), "0");
+
+    file_sync.flush().unwrap();
+    assert!(!file_sync.is_dirty());
+    assert_eq!(on_disk(&fp), "2");
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[test]
+fn flush_without_changes_writes_nothing() {
+    let fp = temp_path("flush_without_changes_writes_nothing");
+    let mut file_sync = FileSync::new(&fp, 0, false).unwrap();
+    std::fs::write(&fp, "5").unwrap();
+
+    file_sync.flush().unwrap();
+
+    assert_eq!(on_disk(&fp), "5");
+    drop(file_sync);
+    std::fs::remove_file(&fp).unwrap();
+}
+
+#[test]
+fn drop_flushes() {
+    let fp = temp_path("drop_flushes");
+    let mut file_sync = FileSync::new(&fp, 0, false).unwrap();
+    file_sync.write_policy = WritePolicy::Manual;
+
+    file_sync.set(1).unwrap();
+    drop(file_sync);
+
+    assert_eq!(on_disk(&fp), "1");
+    std::fs::remove_file(&fp).unwrap();
+}